@@ -0,0 +1,169 @@
+#[macro_use]
+extern crate criterion;
+
+use criterion::Criterion;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokenizers::models::unigram::{EncodeWorkspace, Unigram};
+
+/// Counts every allocation made through the global allocator, so the
+/// `encode_into` vs. `encode_into_with_workspace` benchmark below can show
+/// allocation counts dropping on repeat calls, not just wall time. Scoped to this bench binary only; the library itself keeps using
+/// the system allocator.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn count_allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+/// A small vocab of single chars plus a handful of common short
+/// combinations, big enough to give the trie some real branching without
+/// needing an external vocab file.
+fn bench_vocab() -> Vec<(String, f64)> {
+    let mut vocab = vec![
+        ("<unk>".to_string(), 0.0),
+        ("<s>".to_string(), 0.0),
+        ("</s>".to_string(), 0.0),
+    ];
+    for c in "abcdefghijklmnopqrstuvwxyz ".chars() {
+        vocab.push((c.to_string(), -1.0));
+    }
+    for pair in &["th", "he", "in", "er", "an", "re", "on", "at", "en", "nd"] {
+        vocab.push((pair.to_string(), -1.2));
+    }
+    vocab
+}
+
+/// ~100KB of repeating lowercase text, long enough that a 100KB input
+/// exercises many lattice positions.
+fn bench_input() -> String {
+    "the quick brown fox jumps over the lazy dog and then runs ahead "
+        .repeat(100_000 / 66)
+}
+
+fn bench_unigram(c: &mut Criterion) {
+    let model = Unigram::from(bench_vocab(), Some(0));
+    let input = bench_input();
+
+    // Dominated by `populate_nodes`: one trie `common_prefix_search` per
+    // lattice position over this ~100KB input.
+    c.bench_function("Unigram tokenize (lattice)", |b| {
+        b.iter(|| model.tokenize(&input).unwrap())
+    });
+
+    c.bench_function("Unigram encode_fast (lattice-free)", |b| {
+        b.iter(|| model.encode_fast(&input))
+    });
+}
+
+/// Compares mapping `encode_into` one sentence at a time against
+/// `encode_batch` over the same sentences, to show the throughput `rayon`
+/// buys back once `TOKENIZERS_PARALLELISM` is left at its default.
+fn bench_unigram_batch(c: &mut Criterion) {
+    let model = Unigram::from(bench_vocab(), Some(0));
+    let sentence = "the quick brown fox jumps over the lazy dog";
+    let sentences: Vec<&str> = std::iter::repeat(sentence).take(1_000).collect();
+
+    c.bench_function("Unigram encode one sentence at a time", |b| {
+        b.iter(|| {
+            let mut out = Vec::new();
+            sentences
+                .iter()
+                .map(|s| {
+                    model.encode_into(s, false, &mut out);
+                    out.clone()
+                })
+                .collect::<Vec<_>>()
+        })
+    });
+
+    c.bench_function("Unigram encode_batch (rayon)", |b| {
+        b.iter(|| model.encode_batch(&sentences, false))
+    });
+}
+
+/// Demonstrates that `encode_into_with_workspace` amortizes its buffers
+/// across calls, unlike a fresh-`Vec`-per-call `encode_into`: the first call
+/// allocates to grow the workspace's buffers up to the input's size; every
+/// call after that against same-size-or-smaller input allocates only the
+/// piece `String`s it pushes into `out`.
+fn bench_unigram_allocations(c: &mut Criterion) {
+    let model = Unigram::from(bench_vocab(), Some(0));
+    let sentence = "the quick brown fox jumps over the lazy dog";
+    let mut out = Vec::new();
+
+    let per_call_allocs = count_allocations(|| {
+        model.encode_into(sentence, false, &mut out);
+    });
+    println!("encode_into: {} allocations per call", per_call_allocs);
+
+    let mut ws = EncodeWorkspace::new();
+    model.encode_into_with_workspace(sentence, &mut ws, false, &mut out); // warm up
+    let warm_workspace_allocs = count_allocations(|| {
+        model.encode_into_with_workspace(sentence, &mut ws, false, &mut out);
+    });
+    println!(
+        "encode_into_with_workspace (warm): {} allocations per call",
+        warm_workspace_allocs
+    );
+
+    c.bench_function("Unigram encode_into", |b| {
+        b.iter(|| model.encode_into(sentence, false, &mut out))
+    });
+    c.bench_function("Unigram encode_into_with_workspace", |b| {
+        b.iter(|| model.encode_into_with_workspace(sentence, &mut ws, false, &mut out))
+    });
+}
+
+/// Compares loading a vocab already collected into a `Vec` (`Unigram::from`)
+/// against loading the same vocab straight from an iterator
+/// (`Unigram::from_iter`), to show whether skipping the caller-side
+/// `collect::<Vec<_>>()` actually saves an allocation at this vocab size.
+fn bench_unigram_load(c: &mut Criterion) {
+    let vocab = bench_vocab();
+
+    let from_vec_allocs = count_allocations(|| {
+        Unigram::from(vocab.clone(), Some(0));
+    });
+    println!("Unigram::from: {} allocations to load", from_vec_allocs);
+
+    let from_iter_allocs = count_allocations(|| {
+        Unigram::from_iter(vocab.clone().into_iter(), Some(0));
+    });
+    println!(
+        "Unigram::from_iter: {} allocations to load",
+        from_iter_allocs
+    );
+
+    c.bench_function("Unigram::from", |b| {
+        b.iter(|| Unigram::from(vocab.clone(), Some(0)))
+    });
+    c.bench_function("Unigram::from_iter", |b| {
+        b.iter(|| Unigram::from_iter(vocab.clone().into_iter(), Some(0)))
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = bench_unigram, bench_unigram_batch, bench_unigram_allocations, bench_unigram_load
+}
+criterion_main!(benches);
@@ -0,0 +1,373 @@
+use rand::Rng;
+
+/// A single candidate piece covering `[pos, pos + length)` of a `Lattice`'s
+/// sentence. `piece_id` is `None` when the node stands in for an unknown
+/// (out-of-vocabulary) character.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub piece_id: Option<usize>,
+    pub pos: usize,
+    pub length: usize,
+    pub score: f64,
+}
+
+/// A lattice of every candidate segmentation of a sentence (in chars),
+/// used to run Viterbi search for the single best-scoring path.
+///
+/// Public so code outside this crate can plug in its own scoring or search
+/// (e.g. a beam search, or forward-backward over a different quantity than
+/// [`Lattice::marginal_log_prob`]) on top of the same edges
+/// [`Unigram::populate_nodes`](super::model::Unigram) builds, via
+/// [`Unigram::build_lattice`](super::model::Unigram::build_lattice). Nodes
+/// are addressed by a plain `usize` id rather than a shared-pointer
+/// wrapper; [`Lattice::edges`] and [`Lattice::begin_nodes_at`] are the
+/// intended ways to iterate them without reaching into the lattice's
+/// internal storage.
+#[derive(Debug)]
+pub struct Lattice {
+    chars: Vec<char>,
+    /// Byte offset of each char, plus a trailing entry for the end of the
+    /// sentence, so a char range can be translated back to a byte range.
+    byte_offsets: Vec<usize>,
+    nodes: Vec<Node>,
+    begin_nodes: Vec<Vec<usize>>,
+    end_nodes: Vec<Vec<usize>>,
+}
+
+impl Lattice {
+    pub fn from(sentence: &str) -> Self {
+        let mut chars = vec![];
+        let mut byte_offsets = vec![];
+        for (offset, c) in sentence.char_indices() {
+            byte_offsets.push(offset);
+            chars.push(c);
+        }
+        byte_offsets.push(sentence.len());
+
+        let len = chars.len();
+        Self {
+            chars,
+            byte_offsets,
+            nodes: vec![],
+            begin_nodes: vec![vec![]; len + 1],
+            end_nodes: vec![vec![]; len + 1],
+        }
+    }
+
+    /// Byte offset, in the original sentence, of the char at `pos`. `pos`
+    /// may equal `self.len()`, returning the sentence's total byte length.
+    pub fn byte_offset(&self, pos: usize) -> usize {
+        self.byte_offsets[pos]
+    }
+
+    pub fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    pub fn char_at(&self, pos: usize) -> char {
+        self.chars[pos]
+    }
+
+    /// Insert a candidate node covering `[pos, pos + length)` with the given score.
+    pub fn insert(&mut self, pos: usize, length: usize, score: f64, piece_id: Option<usize>) {
+        let node_id = self.nodes.len();
+        self.nodes.push(Node {
+            piece_id,
+            pos,
+            length,
+            score,
+        });
+        self.begin_nodes[pos].push(node_id);
+        self.end_nodes[pos + length].push(node_id);
+    }
+
+    pub fn node(&self, node_id: usize) -> &Node {
+        &self.nodes[node_id]
+    }
+
+    /// How many candidate nodes have been inserted so far.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Every node beginning at `pos`, as ids usable with `node`/`piece`.
+    pub fn begin_nodes_at(&self, pos: usize) -> &[usize] {
+        &self.begin_nodes[pos]
+    }
+
+    /// Every node ending at `pos`, as ids usable with `node`/`piece`.
+    pub fn end_nodes_at(&self, pos: usize) -> &[usize] {
+        &self.end_nodes[pos]
+    }
+
+    /// Every candidate node inserted so far, as `(node_id, node)` pairs, for
+    /// external code that wants to run its own search over the same edges
+    /// `viterbi` does instead of walking position-by-position.
+    pub fn edges(&self) -> impl Iterator<Item = (usize, &Node)> {
+        self.nodes.iter().enumerate()
+    }
+
+    pub fn piece(&self, node_id: usize) -> String {
+        let node = &self.nodes[node_id];
+        self.chars[node.pos..node.pos + node.length].iter().collect()
+    }
+
+    /// Run Viterbi search and return the node ids making up the best-scoring
+    /// path covering the whole sentence, in order.
+    ///
+    /// TODO: this walks every node once in a single forward pass, which is
+    /// already linear in the number of nodes, but still does the
+    /// straightforward textbook bookkeeping (full `best_score_at`/
+    /// `best_prev_at` vectors). There's a noted opportunity to specialize
+    /// this for the common case of a mostly-linear lattice; see the
+    /// encode_optimized tracking issue.
+    pub fn viterbi(&self) -> Vec<usize> {
+        let len = self.len();
+        let mut best_score_at = vec![std::f64::NEG_INFINITY; len + 1];
+        let mut best_prev_at: Vec<Option<usize>> = vec![None; len + 1];
+        best_score_at[0] = 0.0;
+
+        for pos in 0..=len {
+            if best_score_at[pos] == std::f64::NEG_INFINITY {
+                continue;
+            }
+            for &node_id in &self.begin_nodes[pos] {
+                let node = &self.nodes[node_id];
+                let end = pos + node.length;
+                let candidate = best_score_at[pos] + node.score;
+                if candidate > best_score_at[end] {
+                    best_score_at[end] = candidate;
+                    best_prev_at[end] = Some(node_id);
+                }
+            }
+        }
+
+        let mut path = vec![];
+        let mut pos = len;
+        while pos > 0 {
+            let node_id = best_prev_at[pos]
+                .expect("lattice should have at least one node covering every position");
+            path.push(node_id);
+            pos = self.nodes[node_id].pos;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Whether two or more distinct paths tie for the maximum Viterbi
+    /// score, i.e. the best segmentation isn't unique. Computed by counting
+    /// (rather than just tracking) the number of best-scoring paths during
+    /// the forward DP.
+    pub fn has_ambiguous_best_path(&self) -> bool {
+        let len = self.len();
+        let mut best_score_at = vec![std::f64::NEG_INFINITY; len + 1];
+        let mut best_count_at = vec![0u64; len + 1];
+        best_score_at[0] = 0.0;
+        best_count_at[0] = 1;
+
+        for pos in 0..=len {
+            if best_score_at[pos] == std::f64::NEG_INFINITY {
+                continue;
+            }
+            for &node_id in &self.begin_nodes[pos] {
+                let node = &self.nodes[node_id];
+                let end = pos + node.length;
+                let candidate = best_score_at[pos] + node.score;
+                if candidate > best_score_at[end] {
+                    best_score_at[end] = candidate;
+                    best_count_at[end] = best_count_at[pos];
+                } else if candidate == best_score_at[end] {
+                    best_count_at[end] += best_count_at[pos];
+                }
+            }
+        }
+
+        best_count_at[len] > 1
+    }
+
+    /// The log of the marginal probability of the whole sentence: the
+    /// log-sum-exp of every possible segmentation's score, not just the
+    /// single best (Viterbi) one. Used to score a sentence as a language
+    /// model would, rather than just to pick its best tokenization.
+    pub fn marginal_log_prob(&self) -> f64 {
+        let len = self.len();
+        let mut forward = vec![std::f64::NEG_INFINITY; len + 1];
+        forward[0] = 0.0;
+
+        for pos in 1..=len {
+            let scores: Vec<f64> = self.end_nodes[pos]
+                .iter()
+                .map(|&node_id| {
+                    let node = &self.nodes[node_id];
+                    forward[node.pos] + node.score
+                })
+                .collect();
+            forward[pos] = log_sum_exp(&scores);
+        }
+
+        forward[len]
+    }
+
+    /// The marginal probability of each node, in node-id order, that a path
+    /// sampled at the given `alpha` includes it. Computed via a
+    /// forward-backward pass rather than by actually sampling.
+    pub fn node_marginal_probs(&self, alpha: f64) -> Vec<f64> {
+        let len = self.len();
+
+        let mut forward = vec![std::f64::NEG_INFINITY; len + 1];
+        forward[0] = 0.0;
+        for pos in 1..=len {
+            let scores: Vec<f64> = self.end_nodes[pos]
+                .iter()
+                .map(|&node_id| {
+                    let node = &self.nodes[node_id];
+                    forward[node.pos] + alpha * node.score
+                })
+                .collect();
+            forward[pos] = log_sum_exp(&scores);
+        }
+
+        let mut backward = vec![std::f64::NEG_INFINITY; len + 1];
+        backward[len] = 0.0;
+        for pos in (0..len).rev() {
+            let scores: Vec<f64> = self.begin_nodes[pos]
+                .iter()
+                .map(|&node_id| {
+                    let node = &self.nodes[node_id];
+                    let end = pos + node.length;
+                    backward[end] + alpha * node.score
+                })
+                .collect();
+            backward[pos] = log_sum_exp(&scores);
+        }
+
+        let total = forward[len];
+        self.nodes
+            .iter()
+            .map(|node| {
+                let end = node.pos + node.length;
+                let log_p = forward[node.pos] + alpha * node.score + backward[end] - total;
+                log_p.exp()
+            })
+            .collect()
+    }
+
+    /// The expected number of nodes (pieces) on a path sampled at the given
+    /// `alpha`, computed via forward-backward marginals rather than by
+    /// actually sampling. Used to budget ahead of training-time batches
+    /// under subword regularization.
+    pub fn expected_path_length(&self, alpha: f64) -> f64 {
+        self.node_marginal_probs(alpha).into_iter().sum()
+    }
+
+    /// Sample a segmentation path instead of taking the single best one
+    /// (subword regularization). `alpha` sharpens (>1) or flattens (<1) the
+    /// sampling distribution relative to the raw scores; `rng` drives the
+    /// random choices, so a seeded `rng` makes sampling reproducible.
+    ///
+    /// Uses forward-filtering/backward-sampling: a forward pass computes, for
+    /// every position, the log-sum-exp of every path reaching it (scaled by
+    /// `alpha`), then a backward pass samples an incoming edge at each
+    /// position proportionally to its share of that total.
+    pub fn sample(&self, alpha: f64, rng: &mut impl Rng) -> Vec<usize> {
+        let len = self.len();
+        let mut forward = vec![std::f64::NEG_INFINITY; len + 1];
+        forward[0] = 0.0;
+
+        for pos in 1..=len {
+            let scores: Vec<f64> = self.end_nodes[pos]
+                .iter()
+                .map(|&node_id| {
+                    let node = &self.nodes[node_id];
+                    forward[node.pos] + alpha * node.score
+                })
+                .collect();
+            forward[pos] = log_sum_exp(&scores);
+        }
+
+        let mut path = vec![];
+        let mut pos = len;
+        while pos > 0 {
+            let candidates = &self.end_nodes[pos];
+            let weights: Vec<f64> = candidates
+                .iter()
+                .map(|&node_id| {
+                    let node = &self.nodes[node_id];
+                    (forward[node.pos] + alpha * node.score - forward[pos]).exp()
+                })
+                .collect();
+
+            let total: f64 = weights.iter().sum();
+            let mut draw = rng.gen::<f64>() * total;
+            let mut chosen = *candidates.last().expect("lattice must cover every position");
+            for (&node_id, weight) in candidates.iter().zip(weights.iter()) {
+                draw -= weight;
+                if draw <= 0.0 {
+                    chosen = node_id;
+                    break;
+                }
+            }
+
+            path.push(chosen);
+            pos = self.nodes[chosen].pos;
+        }
+        path.reverse();
+        path
+    }
+}
+
+fn log_sum_exp(scores: &[f64]) -> f64 {
+    let max = scores.iter().cloned().fold(std::f64::NEG_INFINITY, f64::max);
+    if max == std::f64::NEG_INFINITY {
+        return std::f64::NEG_INFINITY;
+    }
+    max + scores.iter().map(|s| (s - max).exp()).sum::<f64>().ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn viterbi_picks_the_highest_scoring_path() {
+        let mut lattice = Lattice::from("ab");
+        lattice.insert(0, 1, -1.0, Some(0)); // "a"
+        lattice.insert(1, 1, -1.0, Some(1)); // "b"
+        lattice.insert(0, 2, -1.5, Some(2)); // "ab", better than "a" + "b" (-2.0)
+
+        let path = lattice.viterbi();
+        let pieces: Vec<String> = path.iter().map(|&id| lattice.piece(id)).collect();
+        assert_eq!(pieces, vec!["ab".to_string()]);
+    }
+
+    #[test]
+    fn edges_enumerates_every_inserted_node() {
+        let mut lattice = Lattice::from("ab");
+        lattice.insert(0, 1, -1.0, Some(0)); // "a"
+        lattice.insert(1, 1, -1.0, Some(1)); // "b"
+        lattice.insert(0, 2, -1.5, Some(2)); // "ab"
+
+        let pieces: Vec<String> = lattice.edges().map(|(id, _)| lattice.piece(id)).collect();
+        assert_eq!(pieces, vec!["a".to_string(), "b".to_string(), "ab".to_string()]);
+    }
+
+    #[test]
+    fn expected_path_length_matches_hand_computation() {
+        let mut lattice = Lattice::from("ab");
+        lattice.insert(0, 1, -1.0, Some(0)); // "a"
+        lattice.insert(1, 1, -1.0, Some(1)); // "b"
+        lattice.insert(0, 2, -1.5, Some(2)); // "ab"
+
+        // Two paths: "a"+"b" (score -2.0, length 2) and "ab" (score -1.5,
+        // length 1). E[length] = (2*e^-2.0 + 1*e^-1.5) / (e^-2.0 + e^-1.5).
+        let w_split = (-2.0f64).exp();
+        let w_whole = (-1.5f64).exp();
+        let hand_computed = (2.0 * w_split + w_whole) / (w_split + w_whole);
+
+        assert!((lattice.expected_path_length(1.0) - hand_computed).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,21 @@
+//! [Unigram language model](https://arxiv.org/abs/1804.10959) tokenizer, as
+//! used by SentencePiece.
+mod lattice;
+mod model;
+mod segments;
+mod serialization;
+#[cfg(feature = "spm")]
+mod spm_proto;
+#[cfg(feature = "toml")]
+mod toml_format;
+mod trainer;
+mod trie;
+
+pub use lattice::{Lattice, Node};
+pub use model::*;
+pub use segments::Segments;
+#[cfg(feature = "spm")]
+pub use spm_proto::load_spm_model;
+#[cfg(feature = "toml")]
+pub use toml_format::{load_toml, save_toml};
+pub use trainer::UnigramTrainer;
@@ -0,0 +1,5132 @@
+use super::lattice::Lattice;
+use super::segments::Segments;
+use super::trie::Trie;
+use crate::tokenizer::{Model, Result, Token};
+use crate::utils::parallelism::*;
+use once_cell::sync::OnceCell;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Penalty (in log-probability space) applied to a character that falls
+/// back to the unknown piece. Mirrors SentencePiece's own constant so that
+/// scores stay comparable across implementations.
+pub(crate) const K_UNK_PENALTY: f64 = 10.0;
+
+/// How the penalty for an unknown-character fallback node is computed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UnkPenaltyMode {
+    /// Every unk node costs the same, regardless of how many bytes it covers.
+    PerToken,
+    /// The penalty scales with the number of UTF-8 bytes the unk node
+    /// covers, matching spm training configs that charge byte fallback
+    /// proportionally to the bytes it consumes.
+    PerByte,
+}
+
+/// How an unknown-character token should be surfaced by
+/// [`Unigram::tokenize_with_unk_behavior`]. `Unigram::tokenize` itself
+/// always behaves like `Passthrough` (the lattice's unk node already holds
+/// the original source slice, not the unk symbol), so this only matters to
+/// a caller that explicitly wants a different surface form.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UnkBehavior {
+    /// Replace the unk token's surface text with the vocab's unk symbol
+    /// (e.g. `<unk>`), losing the original source text.
+    Symbol,
+    /// Keep the original source substring as the token's surface text.
+    /// This is what [`Unigram::tokenize`] already does by default.
+    Passthrough,
+    /// Split the unk token into one token per UTF-8 byte, `<0x##>`-style,
+    /// the same surface form [`Unigram::with_byte_fallback`] produces
+    /// automatically when the vocab has every byte entry — except this
+    /// applies regardless of the `byte_fallback` setting or whether those
+    /// `<0x##>` entries exist in the vocab (falling back to the unk id for
+    /// any byte that has no such entry).
+    Bytes,
+}
+
+/// Which Unicode normalization form [`Unigram::compare_normalizations`]
+/// should apply to its input before tokenizing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Normalization {
+    /// Canonical composition.
+    Nfc,
+    /// Compatibility composition, e.g. folds full-width and other
+    /// compatibility variants to their canonical form.
+    Nfkc,
+    /// Canonical decomposition.
+    Nfd,
+    /// Compatibility decomposition.
+    Nfkd,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    /// A line of the vocab file didn't have the expected `token\tscore` shape.
+    /// Holds the (0-indexed) line number.
+    InvalidLine(usize),
+    /// A line's score column wasn't a valid float. Holds the (0-indexed)
+    /// line number and the text that failed to parse.
+    BadScore(usize, String),
+    /// The same token appeared in more than one shard while loading with
+    /// `load_sharded`.
+    DuplicateToken(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "IoError: {}", e),
+            LoadError::InvalidLine(line) => write!(f, "Invalid vocab line at {}", line),
+            LoadError::BadScore(line, text) => {
+                write!(f, "Invalid score `{}` at line {}", text, line)
+            }
+            LoadError::DuplicateToken(token) => {
+                write!(f, "Token `{}` appears in more than one shard", token)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Lattice node ids and positions are handed out as `u32` (see
+/// [`Token::id`]), so a sentence whose char length doesn't fit in a `u32`
+/// can't be represented safely. This is the resulting hard cap on
+/// `Unigram::tokenize`'s input length.
+pub const MAX_LATTICE_LENGTH: usize = u32::MAX as usize;
+
+#[derive(Debug)]
+pub enum EncodeError {
+    /// The input had more chars than [`MAX_LATTICE_LENGTH`] (or a
+    /// caller-provided cap) can index.
+    InputTooLong { len: usize, max: usize },
+    /// The lattice grew past the node budget set by
+    /// [`Unigram::with_max_lattice_nodes`] before encoding finished.
+    LatticeTooLarge { max: usize },
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EncodeError::InputTooLong { len, max } => write!(
+                f,
+                "Input has {} chars, which exceeds the maximum of {} the lattice index can represent",
+                len, max
+            ),
+            EncodeError::LatticeTooLarge { max } => write!(
+                f,
+                "Lattice grew past the {}-node budget while encoding",
+                max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+#[derive(Debug)]
+pub enum CanonicalizeError {
+    /// [`Unigram::canonicalize_specials`] was asked to move a special
+    /// token that isn't in the vocab.
+    MissingSpecial(String),
+}
+
+impl std::fmt::Display for CanonicalizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CanonicalizeError::MissingSpecial(token) => {
+                write!(f, "Special token `{}` is not in the vocab", token)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CanonicalizeError {}
+
+#[derive(Debug)]
+pub enum SharedTrieError {
+    /// [`Unigram::with_shared_trie`] was given a trie that doesn't
+    /// recognize one of this model's vocab tokens.
+    MissingToken(String),
+}
+
+impl std::fmt::Display for SharedTrieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SharedTrieError::MissingToken(token) => write!(
+                f,
+                "Shared trie doesn't recognize vocab token `{}`",
+                token
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SharedTrieError {}
+
+#[derive(Debug)]
+pub enum SpecialTokenError {
+    /// [`Unigram::from_with_special_tokens`] was asked to resolve a
+    /// special token by a spelling that isn't in the vocab.
+    Missing(String),
+}
+
+impl std::fmt::Display for SpecialTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SpecialTokenError::Missing(token) => {
+                write!(f, "Special token `{}` is not in the vocab", token)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpecialTokenError {}
+
+#[derive(Debug)]
+pub enum AddTokensError {
+    /// [`Unigram::add_tokens`] was given a non-finite score (`NaN` or
+    /// infinite), which would make that token impossible to rank against
+    /// the rest of the vocab during tokenization.
+    InvalidScore { token: String, score: f64 },
+}
+
+impl std::fmt::Display for AddTokensError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AddTokensError::InvalidScore { token, score } => {
+                write!(f, "Token `{}` has a non-finite score {}", token, score)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AddTokensError {}
+
+/// Errors validated for at construction time by [`UnigramBuilder::build`]
+/// (and [`Unigram::try_from`]), in place of the `assert!`/`panic!` a
+/// library shouldn't use to reject bad input.
+#[derive(Debug)]
+pub enum UnigramError {
+    /// A vocab entry is the empty string, which has no chars to push into
+    /// the trie and so can never be matched.
+    EmptyToken { id: usize },
+    /// A vocab entry's score is `NaN` or infinite, so it can never be
+    /// ranked against the rest of the vocab.
+    InvalidScore { token: String, score: f64 },
+    /// `unk_id`/`bos_id`/`eos_id` named a position past the end of the
+    /// vocab.
+    IdOutOfRange {
+        field: &'static str,
+        id: usize,
+        vocab_len: usize,
+    },
+    /// The same token string appears at two different positions in the
+    /// vocab. Left unchecked, `token_to_ids` would silently keep the last
+    /// one while `vocab`/`scores` kept both, so e.g. `populate_nodes_checked`'s
+    /// `debug_assert_eq!(self.vocab[id].0, piece)` could fire on a build
+    /// with debug assertions, while a build that skips them silently
+    /// corrupts lookups instead.
+    DuplicateToken {
+        token: String,
+        first_id: usize,
+        duplicate_id: usize,
+    },
+}
+
+impl std::fmt::Display for UnigramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UnigramError::EmptyToken { id } => {
+                write!(f, "Unigram vocab entry {} is an empty token, which can't be matched", id)
+            }
+            UnigramError::InvalidScore { token, score } => {
+                write!(f, "Token `{}` has a non-finite score {}", token, score)
+            }
+            UnigramError::IdOutOfRange { field, id, vocab_len } => write!(
+                f,
+                "{} {} is out of range for a vocab of {} entries",
+                field, id, vocab_len
+            ),
+            UnigramError::DuplicateToken {
+                token,
+                first_id,
+                duplicate_id,
+            } => write!(
+                f,
+                "Token `{}` appears twice in the vocab, at ids {} and {}",
+                token, first_id, duplicate_id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UnigramError {}
+
+/// SentencePiece's own tag for what a vocabulary entry represents, carried
+/// over from the binary `*.model` protobuf (see
+/// [`super::load_spm_model`]) but lost by the plain-text `spm_export_vocab`
+/// format. `Control` and `UserDefined` pieces (e.g. `<pad>`) must always be
+/// matched whole, never split into characters or emitted as unknown; see
+/// [`Unigram::with_piece_types`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceType {
+    Normal,
+    Unknown,
+    Control,
+    UserDefined,
+    Byte,
+}
+
+#[derive(Debug)]
+pub enum PieceTypeError {
+    /// [`Unigram::with_piece_types`] was given a list whose length doesn't
+    /// match the vocab it's being attached to.
+    LengthMismatch { vocab_len: usize, types_len: usize },
+}
+
+impl std::fmt::Display for PieceTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PieceTypeError::LengthMismatch {
+                vocab_len,
+                types_len,
+            } => write!(
+                f,
+                "Got {} piece types for a vocab of {} entries",
+                types_len, vocab_len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PieceTypeError {}
+
+#[derive(Debug)]
+pub enum SaveLoadMismatch {
+    /// [`Unigram::assert_save_load_stable`] found a sentence whose
+    /// tokenization changed after a save/reload round trip.
+    Diverged {
+        sentence: String,
+        before: Vec<(u32, String)>,
+        after: Vec<(u32, String)>,
+    },
+}
+
+impl std::fmt::Display for SaveLoadMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SaveLoadMismatch::Diverged {
+                sentence,
+                before,
+                after,
+            } => write!(
+                f,
+                "Tokenization of `{}` changed after a save/reload round trip: {:?} became {:?}",
+                sentence, before, after
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SaveLoadMismatch {}
+
+fn check_length(len: usize, max: usize) -> std::result::Result<(), EncodeError> {
+    if len > max {
+        Err(EncodeError::InputTooLong { len, max })
+    } else {
+        Ok(())
+    }
+}
+
+/// What [`Unigram::try_repair`] found and fixed.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RepairReport {
+    pub duplicate_tokens_dropped: Vec<String>,
+}
+
+/// Summary statistics over a vocabulary's scores, as returned by
+/// [`Unigram::score_stats`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub std: f64,
+    pub p50: f64,
+    pub p90: f64,
+}
+
+/// Reusable scratch buffers for [`Unigram::encode_into_with_workspace`], so a
+/// caller tokenizing many short strings (e.g. a serving loop) can amortize
+/// their allocation across calls instead of paying for a fresh `Vec` per
+/// call the way a plain [`Unigram::encode_into`] does.
+///
+/// Per-thread: every call takes the workspace by `&mut`, so it is owned by
+/// one thread and reused
+/// across that thread's calls, not shared across threads concurrently (each
+/// worker thread in a parallel pipeline, e.g. under
+/// [`Unigram::encode_batch`], should hold its own).
+#[derive(Debug, Clone, Default)]
+pub struct EncodeWorkspace {
+    chars: Vec<char>,
+    best_score_at: Vec<f64>,
+    best_prev_at: Vec<Option<(usize, usize, bool)>>,
+    spans: Vec<(usize, usize, bool)>,
+    unk_buffer: String,
+}
+
+impl EncodeWorkspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// A single sampled or encoded piece, together with its id and byte offsets
+/// into the original sentence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedPiece {
+    pub piece: String,
+    pub id: u32,
+    pub offsets: (usize, usize),
+}
+
+/// A failure found by [`Unigram::verify_roundtrip`]: `decode(encode_ids(text))`
+/// didn't reproduce `text`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundtripFailure {
+    pub text: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Builds a [`Unigram`] via chained setters instead of [`Unigram::from`]'s
+/// positional `(vocab, unk_id)` pair, validating every invariant
+/// [`Unigram::try_from`] does (no empty tokens, no non-finite scores,
+/// `unk_id`/`bos_id`/`eos_id` in range) and returning a typed
+/// [`UnigramError`] instead of panicking.
+#[derive(Debug, Clone, Default)]
+pub struct UnigramBuilder {
+    vocab: Vec<(String, f64)>,
+    unk_id: Option<usize>,
+    bos_id: Option<usize>,
+    eos_id: Option<usize>,
+    byte_fallback: bool,
+}
+
+impl UnigramBuilder {
+    /// Start a builder over `vocab`, with every other setting at its
+    /// [`Unigram::from`]-equivalent default (no unk, `bos`/`eos`
+    /// auto-derived from the vocab by [`UnigramBuilder::build`], byte
+    /// fallback off).
+    pub fn new(vocab: Vec<(String, f64)>) -> Self {
+        Self {
+            vocab,
+            ..Default::default()
+        }
+    }
+
+    pub fn unk_id(mut self, unk_id: Option<usize>) -> Self {
+        self.unk_id = unk_id;
+        self
+    }
+
+    pub fn bos_id(mut self, bos_id: Option<usize>) -> Self {
+        self.bos_id = bos_id;
+        self
+    }
+
+    pub fn eos_id(mut self, eos_id: Option<usize>) -> Self {
+        self.eos_id = eos_id;
+        self
+    }
+
+    pub fn byte_fallback(mut self, byte_fallback: bool) -> Self {
+        self.byte_fallback = byte_fallback;
+        self
+    }
+
+    /// Validate every setting and build the `Unigram`, or report the first
+    /// invariant violation found as a [`UnigramError`]. Like [`Unigram::from`],
+    /// `bos_id`/`eos_id` are auto-derived from a `"<s>"`/`"</s>"` vocab entry
+    /// when [`UnigramBuilder::bos_id`]/[`UnigramBuilder::eos_id`] were never
+    /// called, so a builder left at its defaults produces the same model
+    /// `Unigram::from` would.
+    pub fn build(self) -> std::result::Result<Unigram, UnigramError> {
+        let bos_id = self.bos_id.or_else(|| Unigram::find_special(&self.vocab, "<s>"));
+        let eos_id = self.eos_id.or_else(|| Unigram::find_special(&self.vocab, "</s>"));
+        let model = Unigram::try_from(self.vocab, self.unk_id, bos_id, eos_id)?;
+        Ok(model.with_byte_fallback(self.byte_fallback))
+    }
+}
+
+/// A [Unigram language model](https://arxiv.org/abs/1804.10959) tokenizer,
+/// as used by SentencePiece. Unlike `BPE`, it doesn't merge pairs; instead
+/// every vocabulary entry carries a log-probability score and tokenization
+/// picks the highest-scoring segmentation of the input.
+///
+/// `Unigram` is `Send + Sync` (asserted below): once built, nothing about it
+/// is mutated except the lazily-built trie cache, which goes through a
+/// `once_cell::sync::OnceCell` rather than an `Rc`/`RefCell`, so a single
+/// loaded model can safely back a multi-threaded server behind an `Arc`.
+pub struct Unigram {
+    token_to_ids: HashMap<String, u32>,
+    vocab: Vec<(String, f64)>,
+    /// Scores, in id order, mirroring `vocab`. Kept alongside it so
+    /// `scores_slice` can hand out a flat, contiguous `&[f64]` without
+    /// allocating on every call.
+    scores: Vec<f64>,
+    /// SentencePiece piece types, in id order, mirroring `vocab`. Every
+    /// entry defaults to [`PieceType::Normal`] (with `unk_id`, if any,
+    /// defaulting to [`PieceType::Unknown`]) unless overridden via
+    /// [`Unigram::with_piece_types`].
+    piece_types: Vec<PieceType>,
+    /// Built lazily, on first call to `trie()`, so models only ever used
+    /// for id/token lookups (never encoded) skip trie construction
+    /// entirely. Wrapped in an `Arc` so [`Unigram::with_shared_trie`] can
+    /// install one already built (and shared) by another instance instead
+    /// of building a fresh one. The payload at each terminal node is the
+    /// matched token's id, so `populate_nodes_checked` can use a match
+    /// directly instead of re-allocating the piece as a `String` to look it
+    /// up in `token_to_ids`.
+    trie: OnceCell<Arc<Trie<char, u32>>>,
+    /// How many times `trie()` has actually built the trie. Read through the
+    /// `pub(crate)` `trie_build_count()` purely so tests can confirm the
+    /// laziness above.
+    trie_builds: std::sync::atomic::AtomicUsize,
+    unk_id: Option<usize>,
+    /// `None` for a vocab with no `<s>` entry: bos/eos are only needed by
+    /// models whose post-processing doesn't add them itself. See
+    /// [`Unigram::from_with_special_tokens`].
+    bos_id: Option<usize>,
+    eos_id: Option<usize>,
+    unk_penalty_mode: UnkPenaltyMode,
+    /// The penalty subtracted (once, or per byte under
+    /// `UnkPenaltyMode::PerByte`) from an unk fallback node's score.
+    /// Defaults to `K_UNK_PENALTY`; see [`Unigram::set_unk_penalty`].
+    unk_penalty: f64,
+    /// When set, used verbatim as the score of unk fallback nodes, taking
+    /// precedence over `unk_penalty_mode`/`unk_penalty`. See
+    /// [`Unigram::set_unk_score_override`].
+    unk_score_override: Option<f64>,
+    /// When set, `tokenize` fails with [`EncodeError::LatticeTooLarge`]
+    /// instead of growing the lattice past this many nodes. See
+    /// [`Unigram::with_max_lattice_nodes`].
+    max_lattice_nodes: Option<usize>,
+    /// Longest vocabulary entry, in chars. `populate_nodes_checked` never
+    /// asks the trie to match past this many chars from a given position,
+    /// bounding the per-position cost of `common_prefix_search` regardless
+    /// of input length. Derived from `vocab` at construction; see
+    /// [`Unigram::with_max_piece_length`] to override it.
+    max_piece_length: usize,
+    /// When set, an unknown character whose UTF-8 bytes all have a
+    /// `<0x##>` entry in the vocab is tokenized as those byte pieces
+    /// instead of a single `<unk>`, so its original bytes survive a
+    /// decode round-trip. See [`Unigram::with_byte_fallback`].
+    byte_fallback: bool,
+}
+
+impl std::fmt::Debug for Unigram {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("Unigram")
+            .field("vocab", &self.vocab.len())
+            .field("unk_id", &self.unk_id)
+            .finish()
+    }
+}
+
+impl Clone for Unigram {
+    fn clone(&self) -> Self {
+        Self {
+            token_to_ids: self.token_to_ids.clone(),
+            vocab: self.vocab.clone(),
+            scores: self.scores.clone(),
+            piece_types: self.piece_types.clone(),
+            trie: self.trie.clone(),
+            trie_builds: std::sync::atomic::AtomicUsize::new(
+                self.trie_builds.load(std::sync::atomic::Ordering::Relaxed),
+            ),
+            unk_id: self.unk_id,
+            bos_id: self.bos_id,
+            eos_id: self.eos_id,
+            unk_penalty_mode: self.unk_penalty_mode,
+            unk_penalty: self.unk_penalty,
+            unk_score_override: self.unk_score_override,
+            max_lattice_nodes: self.max_lattice_nodes,
+            max_piece_length: self.max_piece_length,
+            byte_fallback: self.byte_fallback,
+        }
+    }
+}
+
+/// Tolerance used by [`PartialEq for Unigram`] when comparing scores, so
+/// that a model surviving a lossy-ish round trip (e.g. through a text
+/// format) still compares equal to the one that produced it.
+const SCORE_EPSILON: f64 = 1e-6;
+
+fn scores_eq(a: f64, b: f64) -> bool {
+    (a - b).abs() <= SCORE_EPSILON
+}
+
+fn optional_scores_eq(a: Option<f64>, b: Option<f64>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => scores_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+impl PartialEq for Unigram {
+    fn eq(&self, other: &Self) -> bool {
+        self.token_to_ids == other.token_to_ids
+            && self.vocab.len() == other.vocab.len()
+            && self
+                .vocab
+                .iter()
+                .zip(other.vocab.iter())
+                .all(|((token, score), (other_token, other_score))| {
+                    token == other_token && scores_eq(*score, *other_score)
+                })
+            && self.piece_types == other.piece_types
+            && self.unk_id == other.unk_id
+            && self.bos_id == other.bos_id
+            && self.eos_id == other.eos_id
+            && self.unk_penalty_mode == other.unk_penalty_mode
+            && scores_eq(self.unk_penalty, other.unk_penalty)
+            && optional_scores_eq(self.unk_score_override, other.unk_score_override)
+            && self.max_lattice_nodes == other.max_lattice_nodes
+            && self.max_piece_length == other.max_piece_length
+            && self.byte_fallback == other.byte_fallback
+    }
+}
+
+/// A single difference between two vocabularies, as reported by
+/// [`Unigram::diff`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum VocabDiff {
+    /// `token` is present in the other model but not in `self`.
+    Added { token: String, score: f64 },
+    /// `token` is present in `self` but not in the other model.
+    Removed { token: String, score: f64 },
+    /// `token` is present in both, but its score differs by more than
+    /// [`SCORE_EPSILON`].
+    Rescored {
+        token: String,
+        old_score: f64,
+        new_score: f64,
+    },
+}
+
+impl Unigram {
+    /// Build a `Unigram` model from a list of `(token, score)` pairs and the
+    /// id of the unknown token, if any. `<s>`/`</s>` are looked up by their
+    /// literal string and are optional: a vocab with neither (e.g. one
+    /// whose bos/eos are instead added by a post-processor) is accepted,
+    /// with [`Unigram::bos_id`]/[`Unigram::eos_id`] simply returning `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `vocab` contains an empty token, a non-finite-score token,
+    /// or the same token twice, or if `unk_id` is out of range: see
+    /// [`Unigram::try_from`] for a variant that reports these as a
+    /// [`UnigramError`] instead.
+    pub fn from(vocab: Vec<(String, f64)>, unk_id: Option<usize>) -> Self {
+        let bos_id = Self::find_special(&vocab, "<s>");
+        let eos_id = Self::find_special(&vocab, "</s>");
+        Self::try_from(vocab, unk_id, bos_id, eos_id).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [`Unigram::from`], but builds `vocab` by draining `iter`
+    /// directly instead of requiring the caller to collect it into a `Vec`
+    /// first. Handy for a vocab that's naturally produced by an iterator
+    /// (e.g. parsing a vocab file line by line) rather than an
+    /// already-materialized `Vec`. The iterator is still collected into a
+    /// `Vec` internally before being handed to [`Unigram::from`], so this
+    /// spares the caller that step, not the allocation.
+    ///
+    /// Note: this doesn't change how many times each token string is
+    /// copied once construction reaches [`Unigram::new_unchecked`], which
+    /// still clones every token into `token_to_ids` alongside the copy
+    /// `vocab` already owns; halving that would mean switching
+    /// `token_to_ids` to a shared (e.g. `Arc<str>`) or index-based
+    /// representation crate-wide, which is a larger change than this
+    /// constructor alone.
+    pub fn from_iter<I: IntoIterator<Item = (String, f64)>>(
+        iter: I,
+        unk_id: Option<usize>,
+    ) -> Self {
+        Self::from(iter.into_iter().collect(), unk_id)
+    }
+
+    /// Like [`Unigram::from`], but validates `vocab` (including that no
+    /// token appears twice) and `unk_id`/`bos_id`/`eos_id` upfront and
+    /// reports the first problem as a [`UnigramError`] instead of
+    /// panicking. Used directly by [`UnigramBuilder::build`].
+    pub fn try_from(
+        vocab: Vec<(String, f64)>,
+        unk_id: Option<usize>,
+        bos_id: Option<usize>,
+        eos_id: Option<usize>,
+    ) -> std::result::Result<Self, UnigramError> {
+        let mut first_id_of = HashMap::new();
+        for (id, (token, score)) in vocab.iter().enumerate() {
+            if token.is_empty() {
+                return Err(UnigramError::EmptyToken { id });
+            }
+            if !score.is_finite() {
+                return Err(UnigramError::InvalidScore {
+                    token: token.clone(),
+                    score: *score,
+                });
+            }
+            if let Some(&first_id) = first_id_of.get(token) {
+                return Err(UnigramError::DuplicateToken {
+                    token: token.clone(),
+                    first_id,
+                    duplicate_id: id,
+                });
+            }
+            first_id_of.insert(token, id);
+        }
+        for (field, id) in [("unk_id", unk_id), ("bos_id", bos_id), ("eos_id", eos_id)] {
+            if let Some(id) = id {
+                if id >= vocab.len() {
+                    return Err(UnigramError::IdOutOfRange {
+                        field,
+                        id,
+                        vocab_len: vocab.len(),
+                    });
+                }
+            }
+        }
+
+        Ok(Self::new_unchecked(vocab, unk_id, bos_id, eos_id))
+    }
+
+    /// Like [`Unigram::from`], but resolves `unk`/`bos`/`eos` by looking
+    /// them up in `vocab` rather than requiring `<s>`/`</s>` by their
+    /// literal spelling, for vocabularies that spell their specials
+    /// differently (e.g. `<|startoftext|>`). Each of `unk`/`bos`/`eos` is
+    /// itself optional (a vocab doesn't have to define all three), but a
+    /// spelling that *is* given and isn't in `vocab` is
+    /// [`SpecialTokenError::Missing`] rather than silently `None`.
+    pub fn from_with_special_tokens(
+        vocab: Vec<(String, f64)>,
+        unk: Option<&str>,
+        bos: Option<&str>,
+        eos: Option<&str>,
+    ) -> std::result::Result<Self, SpecialTokenError> {
+        let find = |token: &str| {
+            vocab
+                .iter()
+                .position(|(t, _)| t == token)
+                .ok_or_else(|| SpecialTokenError::Missing(token.to_owned()))
+        };
+
+        let unk_id = unk.map(find).transpose()?;
+        let bos_id = bos.map(find).transpose()?;
+        let eos_id = eos.map(find).transpose()?;
+
+        Ok(Self::new_unchecked(vocab, unk_id, bos_id, eos_id))
+    }
+
+    /// Look `token` up in `vocab`, returning `None` rather than an error if
+    /// it's missing: used by [`Unigram::from`] and [`UnigramBuilder::build`],
+    /// whose bos/eos are optional (see [`Unigram::from_with_special_tokens`]
+    /// for a variant that treats a given-but-missing spelling as an error
+    /// instead).
+    fn find_special(vocab: &[(String, f64)], token: &str) -> Option<usize> {
+        vocab.iter().position(|(t, _)| t == token)
+    }
+
+    /// Shared construction logic behind [`Unigram::from`] and
+    /// [`Unigram::from_with_special_tokens`], once `unk_id`/`bos_id`/
+    /// `eos_id` have already been resolved.
+    fn new_unchecked(
+        vocab: Vec<(String, f64)>,
+        unk_id: Option<usize>,
+        bos_id: Option<usize>,
+        eos_id: Option<usize>,
+    ) -> Self {
+        let mut token_to_ids = HashMap::new();
+        for (id, (token, _)) in vocab.iter().enumerate() {
+            assert!(
+                !token.is_empty(),
+                "Unigram vocab entry {} is an empty token, which can't be matched",
+                id
+            );
+            token_to_ids.insert(token.to_owned(), id as u32);
+        }
+
+        let max_piece_length = vocab
+            .iter()
+            .map(|(token, _)| token.chars().count())
+            .max()
+            .unwrap_or(0);
+        let scores = vocab.iter().map(|(_, score)| *score).collect();
+        let piece_types = (0..vocab.len())
+            .map(|id| {
+                if Some(id) == unk_id {
+                    PieceType::Unknown
+                } else {
+                    PieceType::Normal
+                }
+            })
+            .collect();
+
+        Self {
+            token_to_ids,
+            vocab,
+            scores,
+            piece_types,
+            trie: OnceCell::new(),
+            trie_builds: std::sync::atomic::AtomicUsize::new(0),
+            unk_id,
+            bos_id,
+            eos_id,
+            unk_penalty_mode: UnkPenaltyMode::PerToken,
+            unk_penalty: K_UNK_PENALTY,
+            unk_score_override: None,
+            max_lattice_nodes: None,
+            max_piece_length,
+            byte_fallback: false,
+        }
+    }
+
+    /// Override the piece types SentencePiece tagged each vocab entry with
+    /// (see [`PieceType`]), e.g. after loading from
+    /// [`super::load_spm_model`]. Consuming, so it chains onto
+    /// [`Unigram::from`]. `piece_types` must have exactly
+    /// `get_vocab_size()` entries, in id order.
+    pub fn with_piece_types(mut self, piece_types: Vec<PieceType>) -> Result<Self> {
+        if piece_types.len() != self.vocab.len() {
+            return Err(Box::new(PieceTypeError::LengthMismatch {
+                vocab_len: self.vocab.len(),
+                types_len: piece_types.len(),
+            }));
+        }
+        self.piece_types = piece_types;
+        Ok(self)
+    }
+
+    /// The [`PieceType`] SentencePiece tagged vocab entry `id` with, or
+    /// `None` if `id` is out of range.
+    pub fn piece_type(&self, id: u32) -> Option<PieceType> {
+        self.piece_types.get(id as usize).copied()
+    }
+
+    /// Cap how many candidate nodes `tokenize` will let the lattice grow to
+    /// before giving up with [`EncodeError::LatticeTooLarge`], as a guard
+    /// against adversarial or pathological inputs (e.g. long runs over a
+    /// vocabulary with many overlapping short pieces) blowing up memory.
+    /// Consuming, so it chains onto [`Unigram::from`]: `Unigram::from(...)
+    /// .with_max_lattice_nodes(1_000_000)`.
+    pub fn with_max_lattice_nodes(mut self, max: usize) -> Self {
+        self.max_lattice_nodes = Some(max);
+        self
+    }
+
+    /// Override the cap on how many chars `populate_nodes_checked` will
+    /// search the trie for past any given position, normally derived from
+    /// the longest vocab entry. Lowering it is a speedup on inputs that
+    /// don't need the full range (at the cost of never matching a piece
+    /// longer than the override); raising it above the true longest piece
+    /// is a no-op. Consuming, so it chains onto [`Unigram::from`].
+    pub fn with_max_piece_length(mut self, max_piece_length: usize) -> Self {
+        self.max_piece_length = max_piece_length;
+        self
+    }
+
+    /// Fall back to per-byte `<0x##>` pieces (SentencePiece's byte-fallback
+    /// scheme) instead of `<unk>` for characters the vocab can't otherwise
+    /// match, as long as every one of those byte pieces is itself in the
+    /// vocab. Consuming, so it chains onto [`Unigram::from`].
+    pub fn with_byte_fallback(mut self, byte_fallback: bool) -> Self {
+        self.byte_fallback = byte_fallback;
+        self
+    }
+
+    /// The ids of `c`'s UTF-8 bytes' `<0x##>` vocab entries (e.g. `<0xE2>`
+    /// for the first byte of a 3-byte UTF-8 sequence), or `None` if
+    /// byte-fallback is disabled or any of those bytes has no such entry.
+    fn byte_fallback_ids(&self, c: char) -> Option<Vec<u32>> {
+        if !self.byte_fallback {
+            return None;
+        }
+        let mut buf = [0u8; 4];
+        c.encode_utf8(&mut buf)
+            .bytes()
+            .map(|byte| {
+                self.token_to_ids
+                    .get(&format!("<0x{:02X}>", byte))
+                    .copied()
+            })
+            .collect()
+    }
+
+    /// The trie over vocabulary tokens, built on first access (or installed
+    /// ahead of time by [`Unigram::with_shared_trie`]), with each token's id
+    /// stored as the payload at its terminal node.
+    fn trie(&self) -> &Trie<char, u32> {
+        self.trie
+            .get_or_init(|| {
+                self.trie_builds
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let mut trie = Trie::new();
+                for (id, (token, _)) in self.vocab.iter().enumerate() {
+                    trie.push_with_value(token.chars(), id as u32);
+                }
+                Arc::new(trie)
+            })
+            .as_ref()
+    }
+
+    /// Use an externally-built trie instead of building one from this
+    /// model's vocab, so several models that share a common base
+    /// vocabulary (e.g. a base plus small per-model deltas) can share the
+    /// one expensive structure via `Arc` instead of each building their
+    /// own full copy. `trie` must recognize every token in this model's
+    /// vocab at the id this model itself assigns it (it may recognize more,
+    /// e.g. tokens from a larger base vocab another model also built from,
+    /// under ids that belong to that other model); any vocab token it
+    /// doesn't recognize, or recognizes under a different id than this
+    /// model's own `token_to_ids`, makes this an error, since trusting a
+    /// mismatched id would silently tokenize against the wrong vocabulary
+    /// entry.
+    pub fn with_shared_trie(mut self, trie: Arc<Trie<char, u32>>) -> Result<Self> {
+        for (token, expected_id) in self.token_to_ids.iter() {
+            let char_count = token.chars().count();
+            let recognized = trie
+                .common_prefix_search(token.chars())
+                .into_iter()
+                .any(|(len, id)| len == char_count && id == *expected_id);
+            if !recognized {
+                return Err(Box::new(SharedTrieError::MissingToken(token.clone())));
+            }
+        }
+        self.trie = OnceCell::from(trie);
+        Ok(self)
+    }
+
+    /// How many times the trie has actually been built. Exposed for tests
+    /// to confirm id/token-only usage never builds it.
+    pub(crate) fn trie_build_count(&self) -> usize {
+        self.trie_builds.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// A content hash of the vocabulary (tokens and scores, in id order),
+    /// used to confirm a serialized trie (see [`Unigram::trie_to_bytes`])
+    /// was actually built from this model's vocab before trusting it.
+    pub fn vocab_fingerprint(&self) -> u64 {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        for (token, score) in &self.vocab {
+            token.hash(&mut hasher);
+            score.to_bits().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Serialize this model's trie, tagged with its vocab fingerprint, so
+    /// another process sharing the same vocab can load it via
+    /// [`Unigram::load_trie_bytes`] instead of rebuilding it. Building the
+    /// trie first, if it hasn't been already.
+    pub fn trie_to_bytes(&self) -> Result<Vec<u8>> {
+        self.trie().to_bytes(self.vocab_fingerprint())
+    }
+
+    /// Install a trie previously produced by [`Unigram::trie_to_bytes`] in
+    /// place of building one from the vocab, for cache-warming a freshly
+    /// loaded model from another process's already-built trie. Fails if the
+    /// serialized trie's fingerprint doesn't match this model's vocab.
+    pub fn load_trie_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        let trie = Trie::from_bytes(bytes, self.vocab_fingerprint())?;
+        self.trie = OnceCell::from(Arc::new(trie));
+        Ok(())
+    }
+
+    /// Configure how the penalty for unknown-character fallback nodes is
+    /// computed. Defaults to [`UnkPenaltyMode::PerToken`]. Has no effect
+    /// once [`Unigram::set_unk_score_override`] is set, since the override
+    /// takes precedence.
+    pub fn set_unk_penalty_mode(&mut self, mode: UnkPenaltyMode) {
+        self.unk_penalty_mode = mode;
+    }
+
+    /// Configure the penalty itself, applied per `unk_penalty_mode`.
+    /// Defaults to `10.0`; a larger value pushes the lattice toward
+    /// segmentations that avoid unk fallback nodes altogether.
+    pub fn set_unk_penalty(&mut self, penalty: f64) {
+        self.unk_penalty = penalty;
+    }
+
+    /// The penalty configured via [`Unigram::set_unk_penalty`] (`10.0` by
+    /// default), applied per `unk_penalty_mode`.
+    pub fn unk_penalty(&self) -> f64 {
+        self.unk_penalty
+    }
+
+    /// Use a fixed score for every unk fallback node instead of deriving one
+    /// from `unk_penalty_mode`, for models trained with an explicit unk
+    /// cost rather than a derived penalty. Set to `None` to go back to
+    /// `unk_penalty_mode`-derived scoring (the default).
+    pub fn set_unk_score_override(&mut self, score: Option<f64>) {
+        self.unk_score_override = score;
+    }
+
+    pub fn bos_id(&self) -> Option<usize> {
+        self.bos_id
+    }
+
+    pub fn eos_id(&self) -> Option<usize> {
+        self.eos_id
+    }
+
+    /// The cap on how many chars `populate_nodes_checked`/`encode_fast`
+    /// search the trie for past any given position. See
+    /// [`Unigram::with_max_piece_length`].
+    pub fn max_piece_length(&self) -> usize {
+        self.max_piece_length
+    }
+
+    pub fn unk_id(&self) -> Option<usize> {
+        self.unk_id
+    }
+
+    /// Whether `id` is one of this model's special ids (`unk_id`/`bos_id`/
+    /// `eos_id`). A post-processor or decoder that wants to skip special
+    /// tokens can use this instead of hardcoding which ids those are.
+    pub fn is_special(&self, id: u32) -> bool {
+        let id = id as usize;
+        self.unk_id == Some(id) || self.bos_id == Some(id) || self.eos_id == Some(id)
+    }
+
+    pub fn vocab(&self) -> &[(String, f64)] {
+        &self.vocab
+    }
+
+    /// The score of the vocabulary entry with the given id, if any.
+    pub fn score_of(&self, id: u32) -> Option<f64> {
+        self.vocab.get(id as usize).map(|(_, score)| *score)
+    }
+
+    /// The score of `token`, if it's in the vocabulary. Like
+    /// [`Unigram::score_of`], but looked up by the token's literal spelling
+    /// instead of its id, for callers (e.g. ranking/pruning tools) that
+    /// already have the string and shouldn't need to resolve an id first.
+    pub fn token_score(&self, token: &str) -> Option<f64> {
+        let id = *self.token_to_ids.get(token)?;
+        self.score_of(id)
+    }
+
+    /// The score of `token`, decomposed into its byte-fallback pieces if
+    /// it's a single unknown character and byte fallback is enabled, or
+    /// `None` if neither applies. Shared by [`Unigram::score_tokens`] so a
+    /// segmentation that leans on byte fallback still scores sensibly
+    /// instead of being rejected outright.
+    fn byte_fallback_score(&self, token: &str) -> Option<f64> {
+        let mut chars = token.chars();
+        let c = chars.next()?;
+        if chars.next().is_some() {
+            return None;
+        }
+        let ids = self.byte_fallback_ids(c)?;
+        Some(ids.iter().filter_map(|&id| self.score_of(id)).sum())
+    }
+
+    /// The total log-probability score of `tokens` under this model, as if
+    /// they were the result of segmenting some sentence: the sum of each
+    /// piece's score. Returns `None` if any piece isn't in the vocabulary
+    /// and can't be decomposed via byte fallback, since there's no score to
+    /// sum in that case. Useful for comparing two candidate segmentations
+    /// of the same text, or validating a tokenization a model didn't itself
+    /// produce.
+    pub fn score_tokens(&self, tokens: &[&str]) -> Option<f64> {
+        tokens.iter().try_fold(0.0, |sum, token| {
+            self.token_score(token)
+                .or_else(|| self.byte_fallback_score(token))
+                .map(|score| sum + score)
+        })
+    }
+
+    /// The Viterbi path score [`Unigram::tokenize`] would assign to
+    /// `sentence`: the sum of every node's score along the best-scoring
+    /// segmentation, including unk fallback penalties. Unlike
+    /// [`Unigram::score_tokens`], this always succeeds, since the lattice
+    /// always has an unk fallback to cover any position nothing else
+    /// matches.
+    pub fn best_score(&self, sentence: &str) -> f64 {
+        let lattice = self.build_lattice(sentence);
+        lattice
+            .viterbi()
+            .into_iter()
+            .map(|node_id| lattice.node(node_id).score)
+            .sum()
+    }
+
+    /// Write this model's vocabulary to SentencePiece's plain-text format,
+    /// the inverse of [`load_spm`]: one `token\tscore` pair per line, in id
+    /// order.
+    ///
+    /// A vocabulary loaded via [`load_spm`] already stores its space marker
+    /// as the literal `▁` character (SentencePiece's own on-disk
+    /// convention, not something `load_spm` adds), so this writes each
+    /// token's bytes as-is rather than translating plain spaces back into
+    /// `▁`; a model built some other way that stores literal spaces in its
+    /// pieces would need [`normalize_for_spm`]-style preprocessing first to
+    /// round-trip cleanly through this format.
+    pub fn save_spm(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        for (token, score) in &self.vocab {
+            writeln!(file, "{}\t{}", token, score)?;
+        }
+        Ok(())
+    }
+
+    /// Compares this model's vocabulary against `other`'s, reporting every
+    /// token that was added, removed, or rescored (beyond [`SCORE_EPSILON`]).
+    /// Useful for CI that wants to catch a retrained tokenizer's vocabulary
+    /// drifting unexpectedly.
+    pub fn diff(&self, other: &Unigram) -> Vec<VocabDiff> {
+        let mut diffs = vec![];
+        for (token, score) in &self.vocab {
+            match other.token_score(token) {
+                Some(other_score) if !scores_eq(*score, other_score) => {
+                    diffs.push(VocabDiff::Rescored {
+                        token: token.clone(),
+                        old_score: *score,
+                        new_score: other_score,
+                    });
+                }
+                Some(_) => {}
+                None => diffs.push(VocabDiff::Removed {
+                    token: token.clone(),
+                    score: *score,
+                }),
+            }
+        }
+        for (token, score) in &other.vocab {
+            if self.token_score(token).is_none() {
+                diffs.push(VocabDiff::Added {
+                    token: token.clone(),
+                    score: *score,
+                });
+            }
+        }
+        diffs
+    }
+
+    /// A flat, id-ordered view of every vocabulary entry's score, for
+    /// callers (e.g. GPU/SIMD decoders) that want to index scores directly
+    /// rather than calling [`Unigram::score_of`] one id at a time.
+    pub fn scores_slice(&self) -> &[f64] {
+        &self.scores
+    }
+
+    /// Populate `lattice` with every vocabulary entry that matches a prefix
+    /// starting at each position, falling back to a single-character unknown
+    /// node wherever nothing in the vocabulary matches.
+    fn populate_nodes(&self, lattice: &mut Lattice) {
+        self.populate_nodes_filtered(lattice, None, None)
+    }
+
+    /// Build and populate a [`Lattice`] over `sentence`, without running
+    /// Viterbi on it: the public entry point for code that wants to run its
+    /// own search (e.g. a custom beam search) over the same edges
+    /// `tokenize` does, via [`Lattice::edges`]/[`Lattice::viterbi`]/
+    /// [`Lattice::node_marginal_probs`].
+    pub fn build_lattice(&self, sentence: &str) -> Lattice {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        lattice
+    }
+
+    /// Byte ranges of `sentence` that [`Unigram::tokenize`] would emit as
+    /// `unk_id`, for data-cleaning pipelines that want to audit vocabulary
+    /// coverage before committing to a model. Respects byte fallback: a
+    /// span covered by `<0x##>` byte pieces is tokenizable (just not as a
+    /// normal vocabulary entry), so it isn't reported as unknown.
+    pub fn unknown_spans(&self, sentence: &str) -> Vec<(usize, usize)> {
+        let lattice = self.build_lattice(sentence);
+        lattice
+            .viterbi()
+            .into_iter()
+            .filter_map(|node_id| {
+                let node = lattice.node(node_id);
+                if node.piece_id.is_some() || self.byte_fallback_ids(lattice.char_at(node.pos)).is_some() {
+                    return None;
+                }
+                Some((
+                    lattice.byte_offset(node.pos),
+                    lattice.byte_offset(node.pos + node.length),
+                ))
+            })
+            .collect()
+    }
+
+    /// Render the lattice `tokenize` would build over `sentence` as a
+    /// Graphviz DOT graph, for debugging why one segmentation won over
+    /// another (e.g. why `"AB"` splits but `"ABC"` doesn't). Every
+    /// candidate node is its own graph node, labeled with its piece and
+    /// score; an edge connects a node to every node that can immediately
+    /// follow it. The winning Viterbi path is highlighted: its nodes are
+    /// filled and its edges are drawn in blue.
+    pub fn lattice_to_dot(&self, sentence: &str) -> String {
+        let lattice = self.build_lattice(sentence);
+        let path = lattice.viterbi();
+        let on_path: std::collections::HashSet<usize> = path.iter().copied().collect();
+        let path_edges: std::collections::HashSet<(usize, usize)> =
+            path.windows(2).map(|w| (w[0], w[1])).collect();
+
+        let mut dot = String::from("digraph Lattice {\n    rankdir=LR;\n");
+        for (node_id, node) in lattice.edges() {
+            let piece = lattice.piece(node_id);
+            let kind = if node.piece_id.is_none() { " (unk)" } else { "" };
+            let style = if on_path.contains(&node_id) {
+                ", style=filled, fillcolor=lightblue"
+            } else {
+                ""
+            };
+            dot.push_str(&format!(
+                "    n{} [label=\"{}{}\\n{:.3}\"{}];\n",
+                node_id, piece, kind, node.score, style
+            ));
+        }
+        for (node_id, node) in lattice.edges() {
+            let end = node.pos + node.length;
+            for &next_id in lattice.begin_nodes_at(end) {
+                let style = if path_edges.contains(&(node_id, next_id)) {
+                    " [color=blue, penwidth=2]"
+                } else {
+                    ""
+                };
+                dot.push_str(&format!("    n{} -> n{}{};\n", node_id, next_id, style));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Like `populate_nodes`, but trie matches whose score falls below
+    /// `min_score` are skipped entirely, forcing the lattice toward shorter
+    /// pieces or unk for rare, low-scoring vocabulary entries.
+    fn populate_nodes_with_threshold(&self, lattice: &mut Lattice, min_score: Option<f64>) {
+        self.populate_nodes_filtered(lattice, min_score, None)
+    }
+
+    /// Shared implementation behind `populate_nodes` and
+    /// `populate_nodes_with_threshold`: trie matches below `min_score`, or
+    /// whose id is in `forbidden`, are skipped, falling back to shorter
+    /// pieces or unk. Single-character vocabulary entries are still exempt
+    /// from `forbidden`-driven unk fallback only if they themselves aren't
+    /// forbidden, so coverage still degrades to unk rather than panicking
+    /// when every matching piece at a position is forbidden.
+    ///
+    /// Callers that can't ever hit a node budget (i.e. pass `max_nodes:
+    /// None` in `populate_nodes_checked`) are infallible; this wrapper
+    /// exists purely so they don't have to handle a `Result` that can never
+    /// be an `Err`.
+    fn populate_nodes_filtered(
+        &self,
+        lattice: &mut Lattice,
+        min_score: Option<f64>,
+        forbidden: Option<&std::collections::HashSet<u32>>,
+    ) {
+        self.populate_nodes_checked(lattice, min_score, forbidden, None)
+            .expect("a None node budget can never be exceeded")
+    }
+
+    /// Core node-insertion loop behind every `populate_nodes*` variant.
+    /// Identical to `populate_nodes_filtered`, except that once `max_nodes`
+    /// is set and the lattice has grown past it, insertion stops early and
+    /// this returns [`EncodeError::LatticeTooLarge`] instead of finishing.
+    fn populate_nodes_checked(
+        &self,
+        lattice: &mut Lattice,
+        min_score: Option<f64>,
+        forbidden: Option<&std::collections::HashSet<u32>>,
+        max_nodes: Option<usize>,
+    ) -> std::result::Result<(), EncodeError> {
+        for pos in 0..lattice.len() {
+            if let Some(max) = max_nodes {
+                if lattice.node_count() > max {
+                    return Err(EncodeError::LatticeTooLarge { max });
+                }
+            }
+            let end = (pos + self.max_piece_length).min(lattice.len());
+            let suffix: Vec<char> = (pos..end).map(|i| lattice.char_at(i)).collect();
+
+            let mut has_single_char_match = false;
+            let mut has_atomic_match = false;
+            for (len, id) in self.trie().common_prefix_search_iter(suffix.iter().copied()) {
+                let id = id as usize;
+                // A shared trie (see `Unigram::with_shared_trie`) may
+                // recognize tokens from a larger base vocab than this
+                // model's own, carrying an id that belongs to that other
+                // model rather than `self`; skip it rather than trusting an
+                // out-of-range id or falling back to unk incorrectly.
+                if id >= self.vocab.len() {
+                    continue;
+                }
+                #[cfg(debug_assertions)]
+                {
+                    let piece: String = suffix[..len].iter().collect();
+                    debug_assert_eq!(
+                        self.vocab[id].0, piece,
+                        "trie payload id {} doesn't match the vocab token it was matched against",
+                        id
+                    );
+                }
+                if let Some(forbidden) = forbidden {
+                    if forbidden.contains(&(id as u32)) {
+                        continue;
+                    }
+                }
+                let (_, score) = &self.vocab[id];
+                if let Some(threshold) = min_score {
+                    if *score < threshold {
+                        continue;
+                    }
+                }
+                if len == 1 {
+                    has_single_char_match = true;
+                }
+                // Control/user-defined pieces (e.g. `<pad>`) must never be
+                // emitted as unknown, whatever their length, so a match on
+                // one of those is as good as a single-char match for
+                // deciding whether this position still needs an unk node.
+                if matches!(
+                    self.piece_types.get(id),
+                    Some(PieceType::Control) | Some(PieceType::UserDefined)
+                ) {
+                    has_atomic_match = true;
+                }
+                lattice.insert(pos, len, *score, Some(id));
+            }
+
+            if !has_single_char_match && !has_atomic_match {
+                let score = match self.unk_score_override {
+                    Some(score) => score,
+                    None => {
+                        let penalty = match self.unk_penalty_mode {
+                            UnkPenaltyMode::PerToken => self.unk_penalty,
+                            UnkPenaltyMode::PerByte => {
+                                self.unk_penalty * lattice.char_at(pos).len_utf8() as f64
+                            }
+                        };
+                        -penalty
+                    }
+                };
+                lattice.insert(pos, 1, score, None);
+            }
+        }
+
+        if let Some(max) = max_nodes {
+            if lattice.node_count() > max {
+                return Err(EncodeError::LatticeTooLarge { max });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compute the [Jaccard index](https://en.wikipedia.org/wiki/Jaccard_index)
+    /// of the vocabularies of `self` and `other`, i.e. how many tokens they
+    /// share relative to how many they have in total.
+    pub fn vocab_jaccard(&self, other: &Unigram) -> f64 {
+        let ours: std::collections::HashSet<&str> =
+            self.vocab.iter().map(|(token, _)| token.as_str()).collect();
+        let theirs: std::collections::HashSet<&str> = other
+            .vocab
+            .iter()
+            .map(|(token, _)| token.as_str())
+            .collect();
+
+        let intersection = ours.intersection(&theirs).count();
+        let union = ours.union(&theirs).count();
+
+        if union == 0 {
+            0.0
+        } else {
+            intersection as f64 / union as f64
+        }
+    }
+
+    /// Count the number of distinct piece ids produced while tokenizing
+    /// `corpus`, as a quick measure of how much of the vocabulary a corpus
+    /// actually exercises.
+    pub fn unique_pieces_used(&self, corpus: &[String]) -> usize {
+        let mut seen = std::collections::HashSet::new();
+        for sentence in corpus {
+            if let Ok(tokens) = self.tokenize(sentence) {
+                seen.extend(tokens.into_iter().map(|token| token.id));
+            }
+        }
+        seen.len()
+    }
+
+    /// List every vocabulary piece made up entirely of characters from the
+    /// given Unicode `script` (e.g. `unicode_script::Script::Hiragana`),
+    /// along with its id. Useful for carving out per-language sub-models.
+    #[cfg(feature = "unicode-script")]
+    pub fn pieces_in_script(&self, script: unicode_script::Script) -> Vec<(&str, u32)> {
+        use unicode_script::UnicodeScript;
+
+        self.vocab
+            .iter()
+            .enumerate()
+            .filter(|(_, (token, _))| {
+                !token.is_empty() && token.chars().all(|c| c.script() == script)
+            })
+            .map(|(id, (token, _))| (token.as_str(), id as u32))
+            .collect()
+    }
+
+    /// Tokenize `sentence`, merging consecutive unk nodes from the Viterbi
+    /// path into a single fused unk piece instead of emitting one per
+    /// character. When `fuse_unk_by_script` is set, a run is also split
+    /// wherever the Unicode script changes, so e.g. a Latin unk run and an
+    /// adjacent CJK unk run stay as two separate pieces instead of one.
+    #[cfg(feature = "unicode-script")]
+    pub fn encode_fuse_unk(&self, sentence: &str, fuse_unk_by_script: bool) -> Vec<String> {
+        use unicode_script::{Script, UnicodeScript};
+
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        let path = lattice.viterbi();
+
+        fn flush(pieces: &mut Vec<String>, buffer: &mut String) {
+            if !buffer.is_empty() {
+                pieces.push(std::mem::take(buffer));
+            }
+        }
+
+        let mut pieces: Vec<String> = vec![];
+        let mut unk_buffer = String::new();
+        let mut unk_script: Option<Script> = None;
+
+        for node_id in path {
+            let piece = lattice.piece(node_id);
+            if lattice.node(node_id).piece_id.is_none() {
+                let script = piece.chars().next().map(|c| c.script());
+                if fuse_unk_by_script && script != unk_script && !unk_buffer.is_empty() {
+                    flush(&mut pieces, &mut unk_buffer);
+                }
+                unk_buffer.push_str(&piece);
+                unk_script = script;
+            } else {
+                flush(&mut pieces, &mut unk_buffer);
+                unk_script = None;
+                pieces.push(piece);
+            }
+        }
+        flush(&mut pieces, &mut unk_buffer);
+
+        pieces
+    }
+
+    /// Like [`Unigram::tokenize`], but merges consecutive unk tokens from
+    /// the Viterbi path into a single fused token, the same way
+    /// [`Unigram::encode_fuse_unk`] merges pieces — except this also keeps
+    /// the fused token's offsets correct: spanning from the first unk
+    /// char's byte start to the last unk char's byte end, even across
+    /// multibyte unknowns (e.g. two consecutive CJK characters), rather
+    /// than losing track of the span once their surface text is
+    /// concatenated.
+    pub fn tokenize_fuse_unk(&self, sequence: &str) -> Result<Vec<Token>> {
+        let tokens = self.tokenize(sequence)?;
+        let mut out = Vec::with_capacity(tokens.len());
+        let mut fused_start: Option<usize> = None;
+        let mut fused_end = 0;
+
+        for token in tokens {
+            if Some(token.id as usize) == self.unk_id {
+                fused_start.get_or_insert(token.offsets.0);
+                fused_end = token.offsets.1;
+            } else {
+                if let Some(start) = fused_start.take() {
+                    out.push(Token::new(
+                        self.unk_id.unwrap() as u32,
+                        sequence[start..fused_end].to_string(),
+                        (start, fused_end),
+                    ));
+                }
+                out.push(token);
+            }
+        }
+        if let Some(start) = fused_start.take() {
+            out.push(Token::new(
+                self.unk_id.unwrap() as u32,
+                sequence[start..fused_end].to_string(),
+                (start, fused_end),
+            ));
+        }
+
+        Ok(out)
+    }
+
+    /// Tokenize each of `words` independently and tag every resulting piece
+    /// with the index of the word it came from, for projecting
+    /// token-classification labels from words onto pieces.
+    pub fn encode_word_aligned(&self, words: &[&str]) -> Vec<(String, usize)> {
+        words
+            .iter()
+            .enumerate()
+            .flat_map(|(word_idx, word)| {
+                let mut lattice = Lattice::from(word);
+                self.populate_nodes(&mut lattice);
+                lattice
+                    .viterbi()
+                    .into_iter()
+                    .map(|node_id| (lattice.piece(node_id), word_idx))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Tokenize `sentence`, but never use a vocabulary piece whose id is in
+    /// `forbidden`, for constrained-decoding experiments that want to
+    /// disable a subset of pieces for one call. Coverage still holds: a
+    /// forbidden multi-char piece just falls back to its shorter
+    /// alternatives or unk, the same as an unscored one would.
+    pub fn encode_masked(&self, sentence: &str, forbidden: &std::collections::HashSet<u32>) -> Vec<String> {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes_filtered(&mut lattice, None, Some(forbidden));
+        lattice
+            .viterbi()
+            .into_iter()
+            .map(|node_id| lattice.piece(node_id))
+            .collect()
+    }
+
+    /// Average edit distance, in pieces, between the sampled segmentation
+    /// (subword regularization, see [`Unigram::sample_encode_detailed`]) and
+    /// the Viterbi best segmentation, over `corpus`. Lower means sampling
+    /// stays close to the best path; higher means it explores more. Useful
+    /// for picking a regularization `alpha`.
+    pub fn sampling_divergence(&self, corpus: &[String], alpha: f64, seed: u64) -> f64 {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+
+        let total: usize = corpus
+            .iter()
+            .map(|sentence| {
+                let mut lattice = Lattice::from(sentence);
+                self.populate_nodes(&mut lattice);
+
+                let viterbi: Vec<String> = lattice
+                    .viterbi()
+                    .into_iter()
+                    .map(|node_id| lattice.piece(node_id))
+                    .collect();
+                let sampled: Vec<String> = lattice
+                    .sample(alpha, &mut rng)
+                    .into_iter()
+                    .map(|node_id| lattice.piece(node_id))
+                    .collect();
+
+                piece_edit_distance(&viterbi, &sampled)
+            })
+            .sum();
+
+        total as f64 / corpus.len() as f64
+    }
+
+    /// Ids of every multi-char piece that's provably never a Viterbi winner,
+    /// regardless of corpus: its score is no better than the best
+    /// alternative segmentation of its own surface form using other
+    /// pieces. A static vocab-quality check, distinct from corpus-based
+    /// dead-piece detection.
+    pub fn provably_dead_pieces(&self) -> Vec<u32> {
+        let mut dead = vec![];
+        for (id, (token, score)) in self.vocab.iter().enumerate() {
+            if token.chars().count() < 2 {
+                continue;
+            }
+
+            let forbidden: std::collections::HashSet<u32> = std::iter::once(id as u32).collect();
+            let mut lattice = Lattice::from(token);
+            self.populate_nodes_filtered(&mut lattice, None, Some(&forbidden));
+            let best_alt_score: f64 = lattice
+                .viterbi()
+                .into_iter()
+                .map(|node_id| lattice.node(node_id).score)
+                .sum();
+
+            if best_alt_score >= *score {
+                dead.push(id as u32);
+            }
+        }
+        dead
+    }
+
+    /// Per-piece inverse document frequency over `corpus`: `ln(N / df)`
+    /// where `df` is the number of sentences a piece's id appears in at
+    /// least once, and `N` is the corpus size. A lightweight building
+    /// block for a retrieval feature layered on top of tokenization.
+    pub fn piece_idf(&self, corpus: &[String]) -> HashMap<u32, f64> {
+        let mut doc_freq: HashMap<u32, usize> = HashMap::new();
+        for sentence in corpus {
+            if let Ok(tokens) = self.tokenize(sentence) {
+                let ids: std::collections::HashSet<u32> =
+                    tokens.into_iter().map(|token| token.id).collect();
+                for id in ids {
+                    *doc_freq.entry(id).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let n = corpus.len() as f64;
+        doc_freq
+            .into_iter()
+            .map(|(id, df)| (id, (n / df as f64).ln()))
+            .collect()
+    }
+
+    /// Reorder the vocab so the tokens named in `order` occupy the leading
+    /// ids, in that order, remapping every id reference consistently
+    /// (`token_to_ids`, cached scores, the trie, and the special-id
+    /// fields). Other per-instance settings (e.g.
+    /// [`Unigram::set_unk_penalty_mode`]) are preserved. Errors if a named
+    /// token isn't in the vocab.
+    pub fn canonicalize_specials(&mut self, order: &[&str]) -> Result<()> {
+        let mut ids_in_order = Vec::with_capacity(order.len());
+        for name in order {
+            let id = self.token_to_ids.get(*name).copied().ok_or_else(|| {
+                Box::new(CanonicalizeError::MissingSpecial(name.to_string()))
+                    as Box<dyn std::error::Error + Send + Sync>
+            })?;
+            ids_in_order.push(id as usize);
+        }
+
+        let leading: std::collections::HashSet<usize> = ids_in_order.iter().copied().collect();
+        let mut new_vocab = Vec::with_capacity(self.vocab.len());
+        for &old_id in &ids_in_order {
+            new_vocab.push(self.vocab[old_id].clone());
+        }
+        for (old_id, entry) in self.vocab.iter().enumerate() {
+            if !leading.contains(&old_id) {
+                new_vocab.push(entry.clone());
+            }
+        }
+
+        let mut remap = vec![0usize; self.vocab.len()];
+        for (new_id, (token, _)) in new_vocab.iter().enumerate() {
+            let old_id = self.token_to_ids[token] as usize;
+            remap[old_id] = new_id;
+        }
+
+        let new_unk_id = self.unk_id.map(|id| remap[id]);
+        let unk_penalty_mode = self.unk_penalty_mode;
+        let unk_penalty = self.unk_penalty;
+        let unk_score_override = self.unk_score_override;
+        let max_lattice_nodes = self.max_lattice_nodes;
+        let max_piece_length = self.max_piece_length;
+        let byte_fallback = self.byte_fallback;
+        let old_piece_types = std::mem::take(&mut self.piece_types);
+
+        *self = Unigram::from(new_vocab, new_unk_id);
+        self.unk_penalty_mode = unk_penalty_mode;
+        self.unk_penalty = unk_penalty;
+        self.unk_score_override = unk_score_override;
+        self.max_lattice_nodes = max_lattice_nodes;
+        self.max_piece_length = max_piece_length;
+        self.byte_fallback = byte_fallback;
+        for (old_id, piece_type) in old_piece_types.into_iter().enumerate() {
+            self.piece_types[remap[old_id]] = piece_type;
+        }
+
+        Ok(())
+    }
+
+    /// Tokenize `sentence` and pair each resulting piece with its marginal
+    /// posterior probability (from forward-backward marginals, normalized
+    /// over every alternative path through the lattice), in `[0, 1]`.
+    /// More interpretable than raw log-scores for a confidence UI.
+    pub fn encode_with_probs(&self, sentence: &str) -> Vec<(String, f64)> {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+
+        let node_probs = lattice.node_marginal_probs(1.0);
+        lattice
+            .viterbi()
+            .into_iter()
+            .map(|node_id| (lattice.piece(node_id), node_probs[node_id]))
+            .collect()
+    }
+
+    /// Build the smallest model that reproduces `self`'s tokenization of
+    /// `sentence`: a vocabulary containing only the pieces that were
+    /// candidates anywhere in the lattice (the Viterbi path plus everything
+    /// that competed with it), keeping the special tokens. Meant for
+    /// shrinking a bug report down to something self-contained.
+    pub fn minimize_for(&self, sentence: &str) -> Unigram {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+
+        let mut keep: std::collections::HashSet<usize> = std::collections::HashSet::new();
+        for id in self.unk_id.into_iter().chain(self.bos_id).chain(self.eos_id) {
+            keep.insert(id);
+        }
+        for pos in 0..lattice.len() {
+            for &node_id in lattice.begin_nodes_at(pos) {
+                if let Some(piece_id) = lattice.node(node_id).piece_id {
+                    keep.insert(piece_id);
+                }
+            }
+        }
+
+        let unk_token = self.unk_id.map(|id| self.vocab[id].0.clone());
+        let vocab: Vec<(String, f64)> = self
+            .vocab
+            .iter()
+            .enumerate()
+            .filter(|(id, _)| keep.contains(id))
+            .map(|(_, (token, score))| (token.clone(), *score))
+            .collect();
+        let new_unk_id = unk_token.and_then(|token| vocab.iter().position(|(t, _)| *t == token));
+
+        Unigram::from(vocab, new_unk_id)
+    }
+
+    /// The expected number of pieces `sentence` would produce under the
+    /// sampling distribution at `alpha` (see
+    /// [`Unigram::sample_encode_detailed`]), computed via forward-backward
+    /// marginals rather than by actually sampling. Useful for sizing
+    /// batches ahead of training with regularization on.
+    pub fn expected_token_count(&self, sentence: &str, alpha: f64) -> f64 {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        lattice.expected_path_length(alpha)
+    }
+
+    /// The top `n` segmentations of `sentence` by total score, descending,
+    /// each paired with that total score. Matches the shape of
+    /// SentencePiece's own `nbest_encode_as_pieces`, for conformance
+    /// testing against it.
+    ///
+    /// Enumerates every segmentation by brute force rather than a proper
+    /// k-best search, so this is only suitable for short inputs or small
+    /// vocabularies.
+    pub fn nbest_with_scores(&self, sentence: &str, n: usize) -> Vec<(Vec<String>, f64)> {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+
+        let mut paths = vec![];
+        enumerate_paths(&lattice, 0, &mut vec![], 0.0, &mut paths);
+        paths.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        paths.truncate(n);
+
+        paths
+            .into_iter()
+            .map(|(path, score)| {
+                (
+                    path.into_iter().map(|node_id| lattice.piece(node_id)).collect(),
+                    score,
+                )
+            })
+            .collect()
+    }
+
+    /// Return a new model with `bias[id]` added to every piece's score, for
+    /// domain adaptation that needs to apply the same per-id adjustment
+    /// across a batch of encodes. Cheaper than rescoring with a closure on
+    /// every call. `bias` must have exactly `get_vocab_size()` entries.
+    ///
+    /// Only the vocabulary and special-token ids carry over; other
+    /// per-instance settings (e.g. [`Unigram::set_unk_penalty_mode`]) reset
+    /// to their defaults, same as any other call to [`Unigram::from`].
+    pub fn with_bias(&self, bias: &[f64]) -> Unigram {
+        assert_eq!(
+            bias.len(),
+            self.vocab.len(),
+            "bias vector length ({}) must match vocab size ({})",
+            bias.len(),
+            self.vocab.len()
+        );
+
+        let vocab = self
+            .vocab
+            .iter()
+            .zip(bias.iter())
+            .map(|((token, score), b)| (token.clone(), score + b))
+            .collect();
+        Unigram::from(vocab, self.unk_id)
+    }
+
+    /// Whether `sentence` has more than one segmentation tied for the
+    /// maximum Viterbi score, i.e. its best tokenization isn't unique.
+    /// Useful for catching reproducibility footguns before they bite.
+    pub fn is_ambiguous(&self, sentence: &str) -> bool {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        lattice.has_ambiguous_best_path()
+    }
+
+    /// Tokenize `sentence` and return each piece as a `(start, end)` byte
+    /// range into `sentence`, rather than an allocated `String`, for the
+    /// lowest-allocation FFI encode path. When `fuse_unk` is set, adjacent
+    /// unk ranges are merged into a single range spanning the whole
+    /// fused region, matching [`Unigram::encode_fuse_unk`]'s piece
+    /// boundaries without requiring the `unicode-script` feature.
+    pub fn encode_ranges(&self, sentence: &str, fuse_unk: bool) -> Vec<(usize, usize)> {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        let path = lattice.viterbi();
+
+        let mut ranges: Vec<(usize, usize)> = vec![];
+        let mut last_was_unk = false;
+        for node_id in path {
+            let node = lattice.node(node_id);
+            let start = lattice.byte_offset(node.pos);
+            let end = lattice.byte_offset(node.pos + node.length);
+            let is_unk = node.piece_id.is_none();
+
+            if fuse_unk && is_unk && last_was_unk {
+                ranges.last_mut().unwrap().1 = end;
+            } else {
+                ranges.push((start, end));
+            }
+            last_was_unk = is_unk;
+        }
+        ranges
+    }
+
+    /// Split `required` into chars that have a single-char vocabulary entry
+    /// and chars that don't, as a pre-deployment check that a model
+    /// supports a target language's alphabet. Returns `(covered,
+    /// uncovered)`.
+    pub fn char_coverage(&self, required: &[char]) -> (Vec<char>, Vec<char>) {
+        required
+            .iter()
+            .copied()
+            .partition(|c| self.token_to_ids.contains_key(&c.to_string()))
+    }
+
+    /// Find every character in `corpus` that has no matching vocabulary
+    /// entry, i.e. would force a char onto the unk path. Adding these chars
+    /// (at minimum) to the vocabulary would let the corpus tokenize with no
+    /// unk at all. Returned in a stable, sorted order.
+    pub fn missing_pieces_for_coverage(&self, corpus: &[String]) -> Vec<String> {
+        let mut missing = std::collections::BTreeSet::new();
+        for sentence in corpus {
+            for c in sentence.chars() {
+                let piece = c.to_string();
+                if !self.token_to_ids.contains_key(&piece) {
+                    missing.insert(piece);
+                }
+            }
+        }
+        missing.into_iter().collect()
+    }
+
+    /// Log marginal probability of `sentence` under the model: the
+    /// log-sum-exp over every possible segmentation's score, rather than
+    /// just the Viterbi best path. Mirrors SentencePiece's own
+    /// `score_sentence_marginal`.
+    pub fn score_sentence_marginal(&self, sentence: &str) -> f64 {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        lattice.marginal_log_prob()
+    }
+
+    /// Average per-char cross-entropy (negative log-likelihood) of `corpus`
+    /// under the model, using [`Unigram::score_sentence_marginal`]. Lower is
+    /// better; this is the standard way to compare two models as language
+    /// models.
+    pub fn corpus_nll(&self, corpus: &[String]) -> f64 {
+        let total_nll: f64 = corpus
+            .iter()
+            .map(|sentence| -self.score_sentence_marginal(sentence))
+            .sum();
+        let total_chars: usize = corpus.iter().map(|sentence| sentence.chars().count()).sum();
+        total_nll / total_chars as f64
+    }
+
+    /// Tokenize `text` and return it as a [`Segments`] rather than a flat
+    /// list, so a caller can look up which piece covers a given byte offset
+    /// (e.g. to map an edit back to the piece it touched).
+    pub fn encode_segments(&self, text: &str) -> Segments {
+        let mut lattice = Lattice::from(text);
+        self.populate_nodes(&mut lattice);
+
+        let pieces = lattice
+            .viterbi()
+            .into_iter()
+            .map(|node_id| {
+                let node = lattice.node(node_id);
+                (
+                    lattice.piece(node_id),
+                    (
+                        lattice.byte_offset(node.pos),
+                        lattice.byte_offset(node.pos + node.length),
+                    ),
+                )
+            })
+            .collect();
+
+        Segments::new(pieces)
+    }
+
+    /// Tokenize `sentence`, forbidding any vocabulary piece whose score is
+    /// below `threshold`. This steers encoding away from rare, low-scoring
+    /// pieces, toward more common ones or unk.
+    pub fn encode_min_score(&self, sentence: &str, threshold: f64) -> Vec<String> {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes_with_threshold(&mut lattice, Some(threshold));
+        lattice
+            .viterbi()
+            .into_iter()
+            .map(|node_id| lattice.piece(node_id))
+            .collect()
+    }
+
+    /// Append `tokens` to the vocabulary in place, each becoming a new
+    /// [`PieceType::Normal`] entry at the next available id. Existing
+    /// entries, including the bos/eos/unk ids, keep their ids unchanged, so
+    /// this is safe to call on a model already in use elsewhere via a
+    /// shared id space. A token already present in the vocab is not
+    /// duplicated: its existing id is returned in its place instead of
+    /// adding a second entry for it. The trie is rebuilt (eagerly, not
+    /// lazily, so the new tokens are matched on the very next call to
+    /// `tokenize`) but `token_to_ids` and the new vocab entries otherwise
+    /// take the usual incremental path.
+    ///
+    /// Returns the id assigned to (or already held by) each token in
+    /// `tokens`, in the same order.
+    pub fn add_tokens(&mut self, tokens: &[(String, f64)]) -> Result<Vec<u32>> {
+        let mut ids = Vec::with_capacity(tokens.len());
+        for (token, score) in tokens {
+            if let Some(&id) = self.token_to_ids.get(token) {
+                ids.push(id);
+                continue;
+            }
+            if !score.is_finite() {
+                return Err(Box::new(AddTokensError::InvalidScore {
+                    token: token.clone(),
+                    score: *score,
+                }));
+            }
+
+            let id = self.vocab.len() as u32;
+            self.vocab.push((token.clone(), *score));
+            self.scores.push(*score);
+            self.piece_types.push(PieceType::Normal);
+            self.token_to_ids.insert(token.clone(), id);
+            self.max_piece_length = self.max_piece_length.max(token.chars().count());
+            ids.push(id);
+        }
+
+        let mut trie = Trie::new();
+        for (id, (token, _)) in self.vocab.iter().enumerate() {
+            trie.push_with_value(token.chars(), id as u32);
+        }
+        self.trie = OnceCell::from(Arc::new(trie));
+
+        Ok(ids)
+    }
+
+    /// Repair a model whose `token_to_ids` lookup or trie might have gone
+    /// stale relative to `vocab` (e.g. after direct field manipulation),
+    /// dropping any duplicate tokens and rebuilding both from scratch. The
+    /// cached `scores` and `piece_types` are re-aligned with the
+    /// deduplicated `vocab` as well.
+    ///
+    /// NOTE: unlike SentencePiece's vocab/scores pair of parallel arrays,
+    /// this `Unigram` keeps `(token, score)` together in one `vocab` vector,
+    /// so they can't individually desync the way a hand-edited JSON with
+    /// separate `vocab`/`scores` keys could. This targets the failure modes
+    /// that can actually occur here.
+    pub fn try_repair(&mut self) -> RepairReport {
+        let mut seen = std::collections::HashSet::new();
+        let mut duplicate_tokens_dropped = vec![];
+        let mut kept = Vec::with_capacity(self.vocab.len());
+        self.vocab.retain(|(token, _)| {
+            if seen.insert(token.clone()) {
+                kept.push(true);
+                true
+            } else {
+                kept.push(false);
+                duplicate_tokens_dropped.push(token.clone());
+                false
+            }
+        });
+        let mut kept = kept.into_iter();
+        self.piece_types.retain(|_| kept.next().unwrap_or(true));
+        self.piece_types.resize(self.vocab.len(), PieceType::Normal);
+        self.scores = self.vocab.iter().map(|(_, score)| *score).collect();
+
+        let mut token_to_ids = HashMap::new();
+        let mut trie = Trie::new();
+        for (id, (token, _)) in self.vocab.iter().enumerate() {
+            token_to_ids.insert(token.clone(), id as u32);
+            trie.push_with_value(token.chars(), id as u32);
+        }
+        self.token_to_ids = token_to_ids;
+        self.trie = OnceCell::from(Arc::new(trie));
+
+        RepairReport {
+            duplicate_tokens_dropped,
+        }
+    }
+
+    /// Shrink the vocabulary to at most `target_size` entries, dropping the
+    /// lowest-scoring pieces first and always keeping `unk_id`/`bos_id`/
+    /// `eos_id` (whichever are set), regardless of their score. A no-op if
+    /// the vocab already fits within `target_size`.
+    ///
+    /// Ids are reassigned to stay contiguous from 0, so any id recorded
+    /// elsewhere (e.g. a `Tokenizer`'s post-processor special-token ids) is
+    /// invalidated by a call to this; it's meant for pruning a freshly
+    /// trained or loaded model, before its ids are handed out to anything
+    /// else.
+    pub fn prune(&mut self, target_size: usize) {
+        if self.vocab.len() <= target_size {
+            return;
+        }
+
+        let special_ids: std::collections::HashSet<usize> = self
+            .unk_id
+            .into_iter()
+            .chain(self.bos_id)
+            .chain(self.eos_id)
+            .collect();
+
+        let mut prunable: Vec<usize> = (0..self.vocab.len())
+            .filter(|id| !special_ids.contains(id))
+            .collect();
+        prunable.sort_by(|&a, &b| self.vocab[b].1.partial_cmp(&self.vocab[a].1).unwrap());
+        prunable.truncate(target_size.saturating_sub(special_ids.len()));
+
+        let mut kept_ids: Vec<usize> = special_ids.iter().copied().collect();
+        kept_ids.extend(prunable);
+        kept_ids.sort_unstable();
+
+        let old_to_new: HashMap<usize, u32> = kept_ids
+            .iter()
+            .enumerate()
+            .map(|(new_id, &old_id)| (old_id, new_id as u32))
+            .collect();
+
+        self.unk_id = self.unk_id.map(|id| old_to_new[&id] as usize);
+        self.bos_id = self.bos_id.map(|id| old_to_new[&id] as usize);
+        self.eos_id = self.eos_id.map(|id| old_to_new[&id] as usize);
+
+        let old_vocab = std::mem::take(&mut self.vocab);
+        self.vocab = kept_ids.into_iter().map(|id| old_vocab[id].clone()).collect();
+        self.scores = self.vocab.iter().map(|(_, score)| *score).collect();
+        self.max_piece_length = self
+            .vocab
+            .iter()
+            .map(|(token, _)| token.chars().count())
+            .max()
+            .unwrap_or(0);
+        self.piece_types = (0..self.vocab.len())
+            .map(|id| {
+                if Some(id) == self.unk_id {
+                    PieceType::Unknown
+                } else {
+                    PieceType::Normal
+                }
+            })
+            .collect();
+
+        let mut token_to_ids = HashMap::new();
+        let mut trie = Trie::new();
+        for (id, (token, _)) in self.vocab.iter().enumerate() {
+            token_to_ids.insert(token.clone(), id as u32);
+            trie.push_with_value(token.chars(), id as u32);
+        }
+        self.token_to_ids = token_to_ids;
+        self.trie = OnceCell::from(Arc::new(trie));
+    }
+
+    /// Decode ids one at a time, yielding each piece's surface form as it
+    /// arrives (with the same `▁`-to-space and byte-fallback handling as
+    /// [`Unigram::decode`]). This enables incremental display during
+    /// generation, without waiting for a whole sequence to finish.
+    ///
+    /// A run of byte-fallback `<0x##>` ids is held back rather than yielded
+    /// byte-by-byte, since a lone byte is rarely valid UTF-8 on its own; it's
+    /// only flushed once a non-byte-fallback id (or the end of `ids`) shows
+    /// the run is complete.
+    pub fn decode_stream<'a>(
+        &'a self,
+        ids: impl Iterator<Item = u32> + 'a,
+    ) -> impl Iterator<Item = String> + 'a {
+        let mut ids = ids;
+        let mut byte_run: Vec<u8> = Vec::new();
+        let mut pending: std::collections::VecDeque<String> = std::collections::VecDeque::new();
+        std::iter::from_fn(move || loop {
+            if let Some(chunk) = pending.pop_front() {
+                return Some(chunk);
+            }
+            match ids.next() {
+                Some(id) => {
+                    let token = self.id_to_token(id).unwrap_or("");
+                    match parse_byte_fallback_token(token) {
+                        Some(byte) => byte_run.push(byte),
+                        None => {
+                            flush_byte_run_into(&mut byte_run, &mut pending);
+                            pending.push_back(token.replace('\u{2581}', " "));
+                        }
+                    }
+                }
+                None => {
+                    flush_byte_run_into(&mut byte_run, &mut pending);
+                    return pending.pop_front();
+                }
+            }
+        })
+    }
+
+    /// Summary statistics over the vocabulary's scores, excluding the
+    /// special `unk`/`bos`/`eos` tokens. Useful for picking a sensible
+    /// threshold for [`Unigram::encode_min_score`].
+    pub fn score_stats(&self) -> ScoreStats {
+        let special_ids: std::collections::HashSet<usize> = self
+            .unk_id
+            .into_iter()
+            .chain(self.bos_id)
+            .chain(self.eos_id)
+            .collect();
+
+        let mut scores: Vec<f64> = self
+            .vocab
+            .iter()
+            .enumerate()
+            .filter(|(id, _)| !special_ids.contains(id))
+            .map(|(_, (_, score))| *score)
+            .collect();
+        scores.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let count = scores.len();
+        let mean = scores.iter().sum::<f64>() / count as f64;
+        let variance = scores.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / count as f64;
+
+        let percentile = |p: f64| -> f64 {
+            let idx = ((count as f64 - 1.0) * p).round() as usize;
+            scores[idx]
+        };
+
+        ScoreStats {
+            min: scores[0],
+            max: scores[count - 1],
+            mean,
+            std: variance.sqrt(),
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+        }
+    }
+
+    /// Compare two corpora's piece distributions under this model via the
+    /// [overlap coefficient](https://en.wikipedia.org/wiki/Overlap_coefficient)
+    /// of their piece-frequency vectors: `sum(min(a[id], b[id])) /
+    /// min(sum(a), sum(b))`. `1.0` means the smaller corpus's piece usage
+    /// is a subset of the larger's; `0.0` means they share no pieces at
+    /// all. Quantifies domain shift as seen by the tokenizer, rather than
+    /// by raw vocabulary.
+    pub fn distribution_overlap(&self, corpus_a: &[String], corpus_b: &[String]) -> f64 {
+        let freq = |corpus: &[String]| -> HashMap<u32, usize> {
+            let mut counts = HashMap::new();
+            for sentence in corpus {
+                if let Ok(tokens) = self.tokenize(sentence) {
+                    for token in tokens {
+                        *counts.entry(token.id).or_insert(0) += 1;
+                    }
+                }
+            }
+            counts
+        };
+
+        let freq_a = freq(corpus_a);
+        let freq_b = freq(corpus_b);
+
+        let total_a: usize = freq_a.values().sum();
+        let total_b: usize = freq_b.values().sum();
+        let min_total = total_a.min(total_b);
+        if min_total == 0 {
+            return 0.0;
+        }
+
+        let overlap: usize = freq_a
+            .iter()
+            .map(|(id, &count_a)| count_a.min(*freq_b.get(id).unwrap_or(&0)))
+            .sum();
+
+        overlap as f64 / min_total as f64
+    }
+
+    /// Tokenize `sentence` and render it as CoNLL-style TSV, one piece per
+    /// line with columns `piece`, `id`, `byte_start`, `byte_end`. Meant for
+    /// NLP tooling interop: an easy format to diff tokenizations or feed
+    /// into other command-line tools.
+    pub fn encode_conll(&self, sentence: &str) -> String {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        let path = lattice.viterbi();
+
+        let mut out = String::new();
+        for node_id in path {
+            let node = lattice.node(node_id);
+            let piece = lattice.piece(node_id);
+            let id = match node.piece_id {
+                Some(id) => id as u32,
+                None => self.unk_id.unwrap_or(0) as u32,
+            };
+            let start = lattice.byte_offset(node.pos);
+            let end = lattice.byte_offset(node.pos + node.length);
+            out.push_str(&format!("{}\t{}\t{}\t{}\n", piece, id, start, end));
+        }
+        out
+    }
+
+    /// A faster path for plain Viterbi encoding (the common `fuse_unk =
+    /// false`, ids-not-needed case): walks the trie once, position by
+    /// position, keeping a single `Vec<f64>` of best scores and
+    /// backpointers, rather than materializing a full [`Lattice`] with its
+    /// `Node`/`begin_nodes`/`end_nodes` bookkeeping. [`Unigram::tokenize`]
+    /// stays the general entry point — it also has to support ids, byte
+    /// offsets, and whatever the full lattice enables (sampling, nbest,
+    /// masking, ...) — but callers that only want the Viterbi pieces
+    /// themselves for a long input can use this instead. See
+    /// SentencePiece's own optimized, lattice-free Viterbi in
+    /// `unigram_model.cc`.
+    pub fn encode_fast(&self, sentence: &str) -> Vec<String> {
+        let chars: Vec<char> = sentence.chars().collect();
+        let len = chars.len();
+
+        let mut best_score_at = vec![std::f64::NEG_INFINITY; len + 1];
+        let mut best_prev_at: Vec<Option<(usize, usize)>> = vec![None; len + 1];
+        best_score_at[0] = 0.0;
+
+        for pos in 0..len {
+            if best_score_at[pos] == std::f64::NEG_INFINITY {
+                continue;
+            }
+
+            let end = (pos + self.max_piece_length).min(len);
+
+            let mut has_single_char_match = false;
+            for (len, id) in self.trie().common_prefix_search_iter(chars[pos..end].iter().copied()) {
+                let id = id as usize;
+                if id >= self.vocab.len() {
+                    continue;
+                }
+                let (_, score) = &self.vocab[id];
+                if len == 1 {
+                    has_single_char_match = true;
+                }
+
+                let end = pos + len;
+                let candidate = best_score_at[pos] + score;
+                if candidate > best_score_at[end] {
+                    best_score_at[end] = candidate;
+                    best_prev_at[end] = Some((pos, len));
+                }
+            }
+
+            if !has_single_char_match {
+                let score = match self.unk_score_override {
+                    Some(score) => score,
+                    None => {
+                        let penalty = match self.unk_penalty_mode {
+                            UnkPenaltyMode::PerToken => self.unk_penalty,
+                            UnkPenaltyMode::PerByte => self.unk_penalty * chars[pos].len_utf8() as f64,
+                        };
+                        -penalty
+                    }
+                };
+
+                let end = pos + 1;
+                let candidate = best_score_at[pos] + score;
+                if candidate > best_score_at[end] {
+                    best_score_at[end] = candidate;
+                    best_prev_at[end] = Some((pos, 1));
+                }
+            }
+        }
+
+        let mut spans = vec![];
+        let mut pos = len;
+        while pos > 0 {
+            let (start, length) = best_prev_at[pos]
+                .expect("lattice should have at least one node covering every position");
+            spans.push((start, length));
+            pos = start;
+        }
+        spans.reverse();
+
+        spans
+            .into_iter()
+            .map(|(start, length)| chars[start..start + length].iter().collect())
+            .collect()
+    }
+
+    /// Longest-match-first encoding: at each position, take the longest
+    /// vocabulary entry starting there (falling back to a single-char unk
+    /// piece if nothing matches), rather than [`Unigram::encode_fast`]'s
+    /// globally highest-scoring segmentation. A purely local, greedy
+    /// strategy: it never reconsiders an earlier choice once made, so for
+    /// instance a long match at one position can force a worse-scoring
+    /// split everywhere after it, where Viterbi would have preferred a
+    /// shorter match there to set up a better one later. Serves callers who
+    /// want WordPiece-like greedy behavior from the same vocab and trie.
+    pub fn encode_greedy(&self, sentence: &str) -> Vec<String> {
+        let chars: Vec<char> = sentence.chars().collect();
+        let len = chars.len();
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < len {
+            let end = (pos + self.max_piece_length).min(len);
+            let longest = self
+                .trie()
+                .common_prefix_search_iter(chars[pos..end].iter().copied())
+                .last();
+            match longest {
+                Some((match_len, _id)) => {
+                    out.push(chars[pos..pos + match_len].iter().collect());
+                    pos += match_len;
+                }
+                None => {
+                    out.push(chars[pos].to_string());
+                    pos += 1;
+                }
+            }
+        }
+        out
+    }
+
+    /// Tokenize `sentence` the usual way when `grapheme_aware` is `false`;
+    /// otherwise, post-process the Viterbi path so that a run of unk pieces
+    /// falling within the same [grapheme
+    /// cluster](https://unicode.org/reports/tr29/) (e.g. a ZWJ emoji
+    /// sequence, which char-level segmentation otherwise splits into its
+    /// component codepoints) is emitted as a single unk piece, rather than
+    /// one per codepoint. Unk runs spanning more than one grapheme cluster
+    /// are still split at the cluster boundary, unlike
+    /// [`Unigram::encode_fuse_unk`].
+    #[cfg(feature = "unicode-segmentation")]
+    pub fn encode_grapheme_aware(&self, sentence: &str, grapheme_aware: bool) -> Vec<String> {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        let path = lattice.viterbi();
+
+        if !grapheme_aware {
+            return path.into_iter().map(|node_id| lattice.piece(node_id)).collect();
+        }
+
+        use unicode_segmentation::UnicodeSegmentation;
+        let boundaries: Vec<usize> = sentence
+            .grapheme_indices(true)
+            .map(|(i, _)| i)
+            .chain(std::iter::once(sentence.len()))
+            .collect();
+        let grapheme_of = |byte_pos: usize| -> usize {
+            boundaries.partition_point(|&b| b <= byte_pos).saturating_sub(1)
+        };
+
+        let mut pieces = vec![];
+        let mut unk_buffer = String::new();
+        let mut unk_grapheme: Option<usize> = None;
+
+        for node_id in path {
+            let node = lattice.node(node_id);
+            let piece = lattice.piece(node_id);
+            if node.piece_id.is_none() {
+                let grapheme = grapheme_of(lattice.byte_offset(node.pos));
+                if unk_grapheme.is_some() && unk_grapheme != Some(grapheme) {
+                    pieces.push(std::mem::take(&mut unk_buffer));
+                }
+                unk_buffer.push_str(&piece);
+                unk_grapheme = Some(grapheme);
+            } else {
+                if !unk_buffer.is_empty() {
+                    pieces.push(std::mem::take(&mut unk_buffer));
+                    unk_grapheme = None;
+                }
+                pieces.push(piece);
+            }
+        }
+        if !unk_buffer.is_empty() {
+            pieces.push(unk_buffer);
+        }
+
+        pieces
+    }
+
+    /// Compute ["fertility"](https://en.wikipedia.org/wiki/Subword_tokenization)
+    /// — average pieces produced per whitespace-separated word — for each
+    /// language in `samples`, a list of `(language_tag, text)` pairs.
+    /// Multiple samples for the same language tag are pooled together
+    /// before averaging. The standard way to compare a tokenizer's
+    /// granularity across languages.
+    pub fn fertility(&self, samples: &[(&str, &str)]) -> HashMap<String, f64> {
+        let mut pieces_and_words: HashMap<String, (usize, usize)> = HashMap::new();
+        for (language, text) in samples {
+            let entry = pieces_and_words
+                .entry(language.to_string())
+                .or_insert((0, 0));
+            for word in text.split_whitespace() {
+                entry.0 += self.tokenize(word).map(|t| t.len()).unwrap_or(0);
+                entry.1 += 1;
+            }
+        }
+
+        pieces_and_words
+            .into_iter()
+            .map(|(language, (pieces, words))| (language, pieces as f64 / words as f64))
+            .collect()
+    }
+
+    /// Tokenize `sentence` and return each piece together with its byte
+    /// offset range in `sentence`, the natural building block for anything
+    /// needing to map a piece back to its source span (`Model::tokenize`
+    /// computes its own offsets and does not fuse unks). Consecutive unk pieces
+    /// are fused into a single piece spanning them all, with their offset
+    /// ranges merged the same way, matching [`Unigram::encode_fuse_unk`]'s
+    /// piece boundaries without requiring the `unicode-script` feature.
+    pub fn encode_with_offsets(&self, sentence: &str) -> Vec<(String, (usize, usize))> {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        let path = lattice.viterbi();
+
+        let mut pieces: Vec<(String, (usize, usize))> = vec![];
+        let mut unk_buffer = String::new();
+        let mut unk_start: Option<usize> = None;
+
+        for node_id in path {
+            let node = lattice.node(node_id);
+            let piece = lattice.piece(node_id);
+            if node.piece_id.is_none() {
+                if unk_start.is_none() {
+                    unk_start = Some(lattice.byte_offset(node.pos));
+                }
+                unk_buffer.push_str(&piece);
+            } else {
+                if let Some(start) = unk_start.take() {
+                    pieces.push((
+                        std::mem::take(&mut unk_buffer),
+                        (start, lattice.byte_offset(node.pos)),
+                    ));
+                }
+                let offsets = (
+                    lattice.byte_offset(node.pos),
+                    lattice.byte_offset(node.pos + node.length),
+                );
+                pieces.push((piece, offsets));
+            }
+        }
+        if let Some(start) = unk_start.take() {
+            pieces.push((unk_buffer, (start, lattice.byte_offset(lattice.len()))));
+        }
+
+        pieces
+    }
+
+    /// Every character that appears as its own length-1 vocabulary piece,
+    /// i.e. the alphabet this model can cover without falling back to unk.
+    /// An input char outside this set can still be covered as part of a
+    /// longer piece, or by `<0x##>` byte pieces under
+    /// [`Unigram::with_byte_fallback`]; otherwise it tokenizes as unk. A
+    /// quick input-compatibility check ahead of
+    /// deploying a model against a new language or corpus.
+    pub fn alphabet(&self) -> std::collections::HashSet<char> {
+        self.vocab
+            .iter()
+            .filter_map(|(token, _)| {
+                let mut chars = token.chars();
+                let first = chars.next()?;
+                if chars.next().is_none() {
+                    Some(first)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Tokenize `sentence` along the Viterbi path, fusing consecutive unk
+    /// pieces only when `fuse_unk` is set (as [`Unigram::encode_fuse_unk`]
+    /// always does, without its script split), but fill `out` instead of
+    /// allocating a fresh `Vec`: `out` is
+    /// cleared first, then its existing capacity is reused for the pieces
+    /// of `sentence`. Meant for a serving loop that reuses one output
+    /// buffer across requests instead of allocating one per call.
+    pub fn encode_into(&self, sentence: &str, fuse_unk: bool, out: &mut Vec<String>) {
+        out.clear();
+
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        let path = lattice.viterbi();
+
+        let mut unk_buffer = String::new();
+        for node_id in path {
+            let piece = lattice.piece(node_id);
+            let is_unk = lattice.node(node_id).piece_id.is_none();
+            if fuse_unk && is_unk {
+                unk_buffer.push_str(&piece);
+            } else {
+                if !unk_buffer.is_empty() {
+                    out.push(std::mem::take(&mut unk_buffer));
+                }
+                out.push(piece);
+            }
+        }
+        if !unk_buffer.is_empty() {
+            out.push(unk_buffer);
+        }
+    }
+
+    /// Like [`Unigram::encode_into`], but runs the lattice-free search
+    /// [`Unigram::encode_fast`] does (a flat Viterbi DP over `chars`
+    /// directly, skipping `Lattice`'s node bookkeeping) and keeps every
+    /// scratch buffer in `ws` between calls instead of allocating fresh
+    /// ones each time. Meant for the same kind of serving loop as
+    /// [`Unigram::encode_into`], just with one more buffer (`ws`) the
+    /// caller is responsible for keeping around and reusing.
+    pub fn encode_into_with_workspace(
+        &self,
+        sentence: &str,
+        ws: &mut EncodeWorkspace,
+        fuse_unk: bool,
+        out: &mut Vec<String>,
+    ) {
+        out.clear();
+
+        ws.chars.clear();
+        ws.chars.extend(sentence.chars());
+        let len = ws.chars.len();
+
+        ws.best_score_at.clear();
+        ws.best_score_at.resize(len + 1, std::f64::NEG_INFINITY);
+        ws.best_prev_at.clear();
+        ws.best_prev_at.resize(len + 1, None);
+        ws.best_score_at[0] = 0.0;
+
+        for pos in 0..len {
+            if ws.best_score_at[pos] == std::f64::NEG_INFINITY {
+                continue;
+            }
+
+            let end = (pos + self.max_piece_length).min(len);
+
+            let mut has_single_char_match = false;
+            for (match_len, id) in self
+                .trie()
+                .common_prefix_search_iter(ws.chars[pos..end].iter().copied())
+            {
+                let id = id as usize;
+                if id >= self.vocab.len() {
+                    continue;
+                }
+                let (_, score) = &self.vocab[id];
+                if match_len == 1 {
+                    has_single_char_match = true;
+                }
+
+                let end = pos + match_len;
+                let candidate = ws.best_score_at[pos] + score;
+                if candidate > ws.best_score_at[end] {
+                    ws.best_score_at[end] = candidate;
+                    ws.best_prev_at[end] = Some((pos, match_len, false));
+                }
+            }
+
+            if !has_single_char_match {
+                let score = match self.unk_score_override {
+                    Some(score) => score,
+                    None => {
+                        let penalty = match self.unk_penalty_mode {
+                            UnkPenaltyMode::PerToken => self.unk_penalty,
+                            UnkPenaltyMode::PerByte => {
+                                self.unk_penalty * ws.chars[pos].len_utf8() as f64
+                            }
+                        };
+                        -penalty
+                    }
+                };
+
+                let end = pos + 1;
+                let candidate = ws.best_score_at[pos] + score;
+                if candidate > ws.best_score_at[end] {
+                    ws.best_score_at[end] = candidate;
+                    ws.best_prev_at[end] = Some((pos, 1, true));
+                }
+            }
+        }
+
+        ws.spans.clear();
+        let mut pos = len;
+        while pos > 0 {
+            let (start, length, is_unk) = ws.best_prev_at[pos]
+                .expect("lattice should have at least one node covering every position");
+            ws.spans.push((start, length, is_unk));
+            pos = start;
+        }
+        ws.spans.reverse();
+
+        ws.unk_buffer.clear();
+        for &(start, length, is_unk) in &ws.spans {
+            let piece: String = ws.chars[start..start + length].iter().collect();
+            if fuse_unk && is_unk {
+                ws.unk_buffer.push_str(&piece);
+            } else {
+                if !ws.unk_buffer.is_empty() {
+                    out.push(std::mem::take(&mut ws.unk_buffer));
+                }
+                out.push(piece);
+            }
+        }
+        if !ws.unk_buffer.is_empty() {
+            out.push(std::mem::take(&mut ws.unk_buffer));
+        }
+    }
+
+    /// Tokenize every sentence in `sentences` independently, spreading the
+    /// work across threads the same way [`crate::Tokenizer::encode_batch`]
+    /// does (governed by the `TOKENIZERS_PARALLELISM` env var rather than a
+    /// compile-time switch, since this crate already links `rayon`
+    /// unconditionally). A `Unigram` never mutates after construction, so
+    /// sharing `&self` across threads is safe.
+    pub fn encode_batch(&self, sentences: &[&str], fuse_unk: bool) -> Vec<Vec<String>> {
+        sentences
+            .maybe_par_iter()
+            .map(|sentence| {
+                let mut out = Vec::new();
+                self.encode_into(sentence, fuse_unk, &mut out);
+                out
+            })
+            .collect()
+    }
+
+    /// Tokenize `reader` one line at a time instead of materializing the
+    /// whole input as a `Lattice`, so memory stays bounded regardless of
+    /// input size. Each line is tokenized with [`Unigram::encode_into`],
+    /// re-run per line.
+    ///
+    /// Tokens never cross a line boundary: a piece that would have spanned
+    /// two lines in a single `encode_into` call is instead cut at the
+    /// newline. Callers streaming something other than natural text (e.g. a
+    /// single unbroken sentence with no line breaks at all) won't see any
+    /// memory benefit and should pre-split on whatever delimiter is safe to
+    /// cut at in their domain.
+    pub fn encode_reader<'a, R: BufRead + 'a>(
+        &'a self,
+        reader: R,
+        fuse_unk: bool,
+    ) -> impl Iterator<Item = Result<String>> + 'a {
+        let mut out = Vec::new();
+        reader.lines().flat_map(move |line| match line {
+            Ok(line) => {
+                self.encode_into(&line, fuse_unk, &mut out);
+                out.drain(..).map(Ok).collect::<Vec<_>>()
+            }
+            Err(e) => vec![Err(e.into())],
+        })
+    }
+
+    /// Measure how much `sentence`'s tokenization changes under small
+    /// perturbations: for each char position, insert a neutral `"x"` char
+    /// and delete the char there, re-tokenize, and average the piece-level
+    /// edit distance from the original segmentation over all of these
+    /// variants. A low score means tokenization boundaries are stable under
+    /// nearby edits; a high score flags a model prone to cascading
+    /// re-segmentation from a single keystroke.
+    pub fn perturbation_sensitivity(&self, sentence: &str) -> f64 {
+        let original = self.tokenize(sentence).unwrap_or_default();
+        let original_pieces: Vec<String> = original.into_iter().map(|t| t.value).collect();
+
+        let chars: Vec<char> = sentence.chars().collect();
+        if chars.is_empty() {
+            return 0.0;
+        }
+
+        let mut variants = vec![];
+        for i in 0..chars.len() {
+            let mut inserted = chars.clone();
+            inserted.insert(i, 'x');
+            variants.push(inserted.into_iter().collect::<String>());
+
+            let mut deleted = chars.clone();
+            deleted.remove(i);
+            variants.push(deleted.into_iter().collect::<String>());
+        }
+
+        let total: usize = variants
+            .iter()
+            .map(|variant| {
+                let pieces: Vec<String> = self
+                    .tokenize(variant)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|t| t.value)
+                    .collect();
+                piece_edit_distance(&original_pieces, &pieces)
+            })
+            .sum();
+
+        total as f64 / variants.len() as f64
+    }
+
+    /// Tokenize `sentence` with `self`, then re-tokenize every fused-unk
+    /// span (see [`Unigram::encode_fuse_unk`]'s boundaries, computed here
+    /// without requiring the `unicode-script` feature) with `fallback`,
+    /// splicing its pieces in place of the single unk piece. Meant for
+    /// mixed-domain input where a specialized model should handle what it
+    /// knows and defer the rest to a general one.
+    ///
+    /// Returns pieces only: `self` and `fallback` may not share an id
+    /// space, so there's no single coherent set of ids to return alongside
+    /// them. Callers that need ids should look each piece up in whichever
+    /// model actually produced it.
+    pub fn encode_with_fallback(&self, sentence: &str, fallback: &Unigram) -> Vec<String> {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+        let path = lattice.viterbi();
+
+        let mut pieces = vec![];
+        let mut unk_start: Option<usize> = None;
+
+        for node_id in path {
+            let node = lattice.node(node_id);
+            if node.piece_id.is_none() {
+                if unk_start.is_none() {
+                    unk_start = Some(node.pos);
+                }
+            } else {
+                if let Some(start) = unk_start.take() {
+                    let from = lattice.byte_offset(start);
+                    let to = lattice.byte_offset(node.pos);
+                    pieces.extend(fallback.tokenize(&sentence[from..to]).unwrap_or_default().into_iter().map(|t| t.value));
+                }
+                pieces.push(lattice.piece(node_id));
+            }
+        }
+        if let Some(start) = unk_start.take() {
+            let from = lattice.byte_offset(start);
+            let to = lattice.byte_offset(lattice.len());
+            pieces.extend(fallback.tokenize(&sentence[from..to]).unwrap_or_default().into_iter().map(|t| t.value));
+        }
+
+        pieces
+    }
+
+    /// Tokenize `text` once per entry in `norms`, normalizing it to that
+    /// form first, so the results can be compared side by side. Useful for
+    /// diagnosing why a model segments a piece of text unexpectedly: e.g. a
+    /// full-width character that NFKC compatibility-folds to its ASCII
+    /// equivalent but NFC leaves untouched can land in the vocab under one
+    /// form and fall back to `unk` under the other.
+    pub fn compare_normalizations(
+        &self,
+        text: &str,
+        norms: &[Normalization],
+    ) -> Vec<(Normalization, Vec<String>)> {
+        norms
+            .iter()
+            .map(|&norm| {
+                let mut normalized: crate::tokenizer::NormalizedString = text.into();
+                match norm {
+                    Normalization::Nfc => normalized.nfc(),
+                    Normalization::Nfkc => normalized.nfkc(),
+                    Normalization::Nfd => normalized.nfd(),
+                    Normalization::Nfkd => normalized.nfkd(),
+                };
+                (norm, self.encode_fast(normalized.get()))
+            })
+            .collect()
+    }
+
+    /// Greedily merge neighboring pieces, left to right, whenever their
+    /// concatenation is itself a vocabulary entry. This is the inverse of
+    /// splitting: it can recover word-level units from a finer segmentation.
+    pub fn merge_adjacent(&self, pieces: Vec<String>) -> Vec<String> {
+        let mut merged: Vec<String> = vec![];
+        for piece in pieces {
+            if let Some(last) = merged.last() {
+                let candidate = format!("{}{}", last, piece);
+                if self.token_to_ids.contains_key(&candidate) {
+                    *merged.last_mut().unwrap() = candidate;
+                    continue;
+                }
+            }
+            merged.push(piece);
+        }
+        merged
+    }
+
+    /// Sample a segmentation of `sentence` (subword regularization) instead
+    /// of taking the single Viterbi best, returning just the piece strings.
+    /// Matches SentencePiece's `SampleEncode`: `alpha` near `0` flattens the
+    /// distribution towards uniform sampling over paths, while a large
+    /// `alpha` sharpens it towards the Viterbi path; `seed` makes the draw
+    /// reproducible. See [`Unigram::sample_encode_detailed`] for ids and
+    /// offsets alongside the pieces.
+    pub fn sample_encode(&self, sentence: &str, alpha: f64, seed: u64) -> Vec<String> {
+        self.sample_encode_detailed(sentence, alpha, seed)
+            .into_iter()
+            .map(|encoded| encoded.piece)
+            .collect()
+    }
+
+    /// Sample a segmentation of `sentence` (subword regularization) and
+    /// return each sampled piece with its id and byte offsets, in one call.
+    /// `alpha` controls the sharpness of the sampling distribution and
+    /// `seed` makes the draw reproducible.
+    pub fn sample_encode_detailed(&self, sentence: &str, alpha: f64, seed: u64) -> Vec<EncodedPiece> {
+        let mut lattice = Lattice::from(sentence);
+        self.populate_nodes(&mut lattice);
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        lattice
+            .sample(alpha, &mut rng)
+            .into_iter()
+            .map(|node_id| {
+                let node = lattice.node(node_id);
+                let id = match node.piece_id {
+                    Some(id) => id as u32,
+                    None => self.unk_id.unwrap_or(0) as u32,
+                };
+                EncodedPiece {
+                    piece: lattice.piece(node_id),
+                    id,
+                    offsets: (
+                        lattice.byte_offset(node.pos),
+                        lattice.byte_offset(node.pos + node.length),
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    /// Like [`Unigram::tokenize`], but lets the caller override the surface
+    /// form of unk tokens via `unk_behavior` instead of always keeping the
+    /// original source substring. A no-op pass-through wrapper when
+    /// `unk_behavior` is [`UnkBehavior::Passthrough`], since that's already
+    /// what `tokenize` itself returns.
+    pub fn tokenize_with_unk_behavior(
+        &self,
+        sequence: &str,
+        unk_behavior: UnkBehavior,
+    ) -> Result<Vec<Token>> {
+        let tokens = self.tokenize(sequence)?;
+        if unk_behavior == UnkBehavior::Passthrough {
+            return Ok(tokens);
+        }
+
+        let mut out = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            if Some(token.id as usize) != self.unk_id {
+                out.push(token);
+                continue;
+            }
+            match unk_behavior {
+                UnkBehavior::Passthrough => unreachable!(),
+                UnkBehavior::Symbol => {
+                    let symbol = self.id_to_token(token.id).unwrap_or("<unk>").to_string();
+                    out.push(Token::new(token.id, symbol, token.offsets));
+                }
+                UnkBehavior::Bytes => {
+                    let (start, _) = token.offsets;
+                    let mut offset = start;
+                    for byte in token.value.as_bytes() {
+                        let piece = format!("<0x{:02X}>", byte);
+                        let id = self.token_to_ids.get(&piece).copied().unwrap_or(token.id);
+                        out.push(Token::new(id, piece, (offset, offset + 1)));
+                        offset += 1;
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Tokenize `sentence` and return just the resulting ids. Ids come
+    /// straight off the winning lattice nodes inside `tokenize`, so this
+    /// already avoids mapping each piece's text back through
+    /// `token_to_id` (an extra `HashMap` lookup per token that would
+    /// otherwise double the hashing work).
+    pub fn encode_ids(&self, sentence: &str) -> Vec<u32> {
+        self.tokenize(sentence)
+            .map(|tokens| tokens.into_iter().map(|token| token.id).collect())
+            .unwrap_or_default()
+    }
+
+    /// Reconstruct a string by concatenating the surface form of each id,
+    /// reversing the two transforms `tokenize` can apply: the SentencePiece
+    /// space marker `▁` is turned back into a literal space, and runs of
+    /// byte-fallback `<0x##>` pieces (see [`Unigram::with_byte_fallback`])
+    /// are reassembled into their raw bytes and UTF-8 decoded. Ids with no
+    /// vocab entry are skipped.
+    pub fn decode(&self, ids: &[u32]) -> String {
+        let mut decoded = String::new();
+        let mut byte_run = Vec::new();
+
+        for &id in ids {
+            let token = match self.id_to_token(id) {
+                Some(token) => token,
+                None => continue,
+            };
+            match parse_byte_fallback_token(token) {
+                Some(byte) => byte_run.push(byte),
+                None => {
+                    flush_byte_run(&mut byte_run, &mut decoded);
+                    decoded.push_str(&token.replace('\u{2581}', " "));
+                }
+            }
+        }
+        flush_byte_run(&mut byte_run, &mut decoded);
+
+        decoded
+    }
+
+    /// For each sentence in `corpus`, verify that decoding its encoded ids
+    /// reproduces the original text, returning one [`RoundtripFailure`] per
+    /// sentence that doesn't round-trip. This is a CI-style sanity check
+    /// that a model's vocabulary covers the text it's meant to tokenize.
+    pub fn verify_roundtrip(&self, corpus: &[String]) -> Vec<RoundtripFailure> {
+        corpus
+            .iter()
+            .filter_map(|text| {
+                let decoded = self.decode(&self.encode_ids(text));
+                if decoded == *text {
+                    None
+                } else {
+                    Some(RoundtripFailure {
+                        text: text.clone(),
+                        expected: text.clone(),
+                        actual: decoded,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Save `self` to a temp directory, reload it, and assert that every
+    /// sentence in `corpus` tokenizes identically before and after,
+    /// returning the first divergence found.
+    ///
+    /// This is a final integration guard against serialization bugs, but
+    /// it can only be as faithful as [`Model::save`] itself: today that
+    /// only writes the plain vocabulary and scores, not `unk_id`/`bos_id`/
+    /// `eos_id`, so reloading re-derives them the same way [`load_spm`]
+    /// does (bos/eos looked up by their `<s>`/`</s>` spelling, unk fixed at
+    /// `0`).
+    /// A model whose special ids don't match that convention will
+    /// correctly fail this check until `save`/load round-trip that
+    /// envelope too.
+    pub fn assert_save_load_stable(&self, corpus: &[String]) -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let saved = self.save(dir.path(), None)?;
+        let vocab_path = saved
+            .first()
+            .expect("Unigram::save always returns exactly one path");
+        let bytes = std::fs::read(vocab_path)?;
+        let vocab: Vec<(String, f64)> = serde_json::from_slice(&bytes)?;
+        let reloaded = Unigram::from(vocab, Some(0));
+
+        for sentence in corpus {
+            let before: Vec<(u32, String)> = self
+                .tokenize(sentence)?
+                .into_iter()
+                .map(|token| (token.id, token.value))
+                .collect();
+            let after: Vec<(u32, String)> = reloaded
+                .tokenize(sentence)?
+                .into_iter()
+                .map(|token| (token.id, token.value))
+                .collect();
+            if before != after {
+                return Err(Box::new(SaveLoadMismatch::Diverged {
+                    sentence: sentence.clone(),
+                    before,
+                    after,
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The top `n` segmentations of `sentence` by total score, descending,
+    /// dropping the scores [`Unigram::nbest_with_scores`] pairs them with.
+    /// Matches the shape of SentencePiece's `NBestEncode` for callers that
+    /// only want the piece sequences. Returns fewer than `n` entries if
+    /// fewer than `n` distinct segmentations exist.
+    pub fn encode_nbest(&self, sentence: &str, n: usize) -> Vec<Vec<String>> {
+        self.nbest_with_scores(sentence, n)
+            .into_iter()
+            .map(|(pieces, _score)| pieces)
+            .collect()
+    }
+}
+
+/// Depth-first enumeration of every path through `lattice` from `pos` to the
+/// end, used by `Unigram::nbest_with_scores`.
+fn enumerate_paths(
+    lattice: &Lattice,
+    pos: usize,
+    current: &mut Vec<usize>,
+    score: f64,
+    results: &mut Vec<(Vec<usize>, f64)>,
+) {
+    if pos == lattice.len() {
+        results.push((current.clone(), score));
+        return;
+    }
+    for &node_id in lattice.begin_nodes_at(pos) {
+        let node = lattice.node(node_id);
+        current.push(node_id);
+        enumerate_paths(lattice, pos + node.length, current, score + node.score, results);
+        current.pop();
+    }
+}
+
+/// Levenshtein distance between two piece sequences, treating each piece as
+/// an atomic unit (rather than operating char-by-char).
+fn piece_edit_distance(a: &[String], b: &[String]) -> usize {
+    let (n, m) = (a.len(), b.len());
+    let mut dp = vec![vec![0usize; m + 1]; n + 1];
+    for (i, row) in dp.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for j in 0..=m {
+        dp[0][j] = j;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            dp[i][j] = if a[i - 1] == b[j - 1] {
+                dp[i - 1][j - 1]
+            } else {
+                1 + dp[i - 1][j].min(dp[i][j - 1]).min(dp[i - 1][j - 1])
+            };
+        }
+    }
+    dp[n][m]
+}
+
+/// The byte a `<0x##>` byte-fallback token (see
+/// [`Unigram::with_byte_fallback`]) stands for, or `None` if `token` isn't
+/// one.
+fn parse_byte_fallback_token(token: &str) -> Option<u8> {
+    let hex = token.strip_prefix("<0x")?.strip_suffix('>')?;
+    if hex.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+/// Append `byte_run`'s bytes to `decoded` as (lossily) decoded UTF-8, then
+/// clear it, ready for the next run. A no-op if `byte_run` is empty.
+fn flush_byte_run(byte_run: &mut Vec<u8>, decoded: &mut String) {
+    if !byte_run.is_empty() {
+        decoded.push_str(&String::from_utf8_lossy(byte_run));
+        byte_run.clear();
+    }
+}
+
+/// Like `flush_byte_run`, but for [`Unigram::decode_stream`], which yields
+/// its decoded chunks through a queue rather than appending to one string.
+fn flush_byte_run_into(byte_run: &mut Vec<u8>, pending: &mut std::collections::VecDeque<String>) {
+    if !byte_run.is_empty() {
+        pending.push_back(String::from_utf8_lossy(byte_run).into_owned());
+        byte_run.clear();
+    }
+}
+
+/// Split a `spm_export_vocab` line into its `(token, score)` columns. The
+/// score is always the last column, so a tab-containing token (legitimate:
+/// some exported vocabularies include one) is split correctly by splitting
+/// on the *last* tab rather than the first. Falls back to splitting on the
+/// last run of whitespace for exporters that separate columns with spaces
+/// instead of a tab.
+fn split_token_and_score(line: &str) -> Option<(&str, &str)> {
+    line.rsplit_once('\t')
+        .or_else(|| line.rsplit_once(char::is_whitespace))
+}
+
+fn parse_spm_vocab(path: &Path) -> std::result::Result<Vec<(String, f64)>, LoadError> {
+    let file = File::open(path).map_err(LoadError::Io)?;
+    let reader = BufReader::new(file);
+
+    let mut vocab = vec![];
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.map_err(LoadError::Io)?;
+        let (token, score) =
+            split_token_and_score(&line).ok_or(LoadError::InvalidLine(line_number))?;
+        let score: f64 = score
+            .parse()
+            .map_err(|_| LoadError::BadScore(line_number, score.to_owned()))?;
+        assert!(
+            !token.is_empty(),
+            "empty token at line {} of {}",
+            line_number,
+            path.display()
+        );
+        vocab.push((token.to_owned(), score));
+    }
+
+    Ok(vocab)
+}
+
+/// Reproduce SentencePiece's default input preprocessing ahead of encoding
+/// with a model loaded via [`load_spm`]: NFKC-normalize, collapse runs of
+/// whitespace to a single space (`remove_extra_whitespaces`), then replace
+/// every space with the `▁` space marker, including a leading one inserted
+/// as a "dummy prefix" (`add_dummy_prefix`) so the first word is marked the
+/// same as every other (`escape_whitespaces`).
+///
+/// [`Unigram::tokenize`] itself never applies this implicitly: it's a pure
+/// function of whatever text it's given, so callers whose pipeline already
+/// normalizes and space-marks text upstream (e.g. via
+/// [`crate::normalizers::unicode::NFKC`] and
+/// [`crate::pre_tokenizers::metaspace::Metaspace`]) don't get it silently
+/// applied twice. Call this first when encoding directly against a model
+/// loaded via [`load_spm`]/[`load_sharded`] with no such pipeline in front
+/// of it.
+pub fn normalize_for_spm(sentence: &str) -> String {
+    let mut normalized: crate::tokenizer::NormalizedString = sentence.into();
+    normalized.nfkc();
+
+    let collapsed = normalized.get().split_whitespace().collect::<Vec<_>>().join(" ");
+    format!(" {}", collapsed).replace(' ', "\u{2581}")
+}
+
+/// Load a `Unigram` model from SentencePiece's plain-text vocab format, as
+/// produced by `spm_export_vocab`: one `token\tscore` pair per line.
+pub fn load_spm(path: &Path) -> Result<Unigram> {
+    Ok(Unigram::from(parse_spm_vocab(path)?, Some(0)))
+}
+
+
+/// Names of the special tokens to look up once shards have been concatenated.
+pub struct SpecialTokens {
+    pub unk_token: String,
+}
+
+/// Load a `Unigram` model whose vocabulary is split across several
+/// plain-text shard files (see [`load_spm`] for the format). Shards are
+/// concatenated in the given order, with ids assigned contiguously, after
+/// checking that no token appears in more than one shard.
+pub fn load_sharded(paths: &[&Path], specials: SpecialTokens) -> Result<Unigram> {
+    let mut vocab = vec![];
+    let mut seen = std::collections::HashSet::new();
+    for path in paths {
+        for (token, score) in parse_spm_vocab(path)? {
+            if !seen.insert(token.clone()) {
+                return Err(Box::new(LoadError::DuplicateToken(token)));
+            }
+            vocab.push((token, score));
+        }
+    }
+
+    let unk_id = vocab
+        .iter()
+        .position(|(token, _)| *token == specials.unk_token);
+    Ok(Unigram::from(vocab, unk_id))
+}
+
+impl Model for Unigram {
+    /// Offsets on the returned `Token`s are always relative to `sequence`
+    /// itself, starting at `0`, never to some caller-supplied base: the
+    /// `Model` trait only ever sees one pre-tokenizer split's text, not the
+    /// whole original string, so it has no such base to honor. Rebasing
+    /// each split's token offsets onto the original string's coordinates is
+    /// [`crate::tokenizer::PreTokenizedString::into_encoding`]'s job, run
+    /// once per split after every split has been tokenized.
+    fn tokenize(&self, sequence: &str) -> Result<Vec<Token>> {
+        check_length(sequence.chars().count(), MAX_LATTICE_LENGTH)?;
+
+        let mut lattice = Lattice::from(sequence);
+        self.populate_nodes_checked(&mut lattice, None, None, self.max_lattice_nodes)?;
+
+        let path = lattice.viterbi();
+        let mut tokens = Vec::with_capacity(path.len());
+        for node_id in path {
+            let node = lattice.node(node_id);
+            let piece = lattice.piece(node_id);
+            let start = lattice.byte_offset(node.pos);
+            let end = lattice.byte_offset(node.pos + node.length);
+
+            if node.piece_id.is_none() {
+                if let Some(byte_ids) = self.byte_fallback_ids(lattice.char_at(node.pos)) {
+                    let mut offset = start;
+                    for (i, id) in byte_ids.into_iter().enumerate() {
+                        let byte_value = piece.as_bytes()[i];
+                        tokens.push(Token::new(
+                            id,
+                            format!("<0x{:02X}>", byte_value),
+                            (offset, offset + 1),
+                        ));
+                        offset += 1;
+                    }
+                    continue;
+                }
+            }
+
+            let id = match node.piece_id {
+                Some(id) => id as u32,
+                None => self.unk_id.unwrap_or(0) as u32,
+            };
+            tokens.push(Token::new(id, piece, (start, end)));
+        }
+        Ok(tokens)
+    }
+
+    fn token_to_id(&self, token: &str) -> Option<u32> {
+        self.token_to_ids.get(token).copied()
+    }
+
+    fn id_to_token(&self, id: u32) -> Option<&str> {
+        self.vocab.get(id as usize).map(|(token, _)| token.as_str())
+    }
+
+    fn get_vocab(&self) -> &HashMap<String, u32> {
+        &self.token_to_ids
+    }
+
+    fn get_vocab_size(&self) -> usize {
+        self.vocab.len()
+    }
+
+    fn save(&self, folder: &Path, name: Option<&str>) -> Result<Vec<PathBuf>> {
+        let vocab_file_name = match name {
+            Some(name) => format!("{}-unigram.json", name),
+            None => "unigram.json".to_string(),
+        };
+
+        let vocab_path: PathBuf = [folder, Path::new(vocab_file_name.as_str())]
+            .iter()
+            .collect();
+        let mut vocab_file = std::fs::File::create(&vocab_path)?;
+        let serialized = serde_json::to_string(&self.vocab)?;
+        std::io::Write::write_all(&mut vocab_file, serialized.as_bytes())?;
+
+        Ok(vec![vocab_path])
+    }
+}
+
+/// Compile-time check that `Unigram` is safe to share across threads (e.g.
+/// wrapped in an `Arc` for a web server's handlers): this fails to compile
+/// if a future change makes `Unigram` neither `Send` nor `Sync`.
+const _: fn() = || {
+    fn assert_send_and_sync<T: Send + Sync>() {}
+    assert_send_and_sync::<Unigram>();
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn sample_vocab() -> Vec<(String, f64)> {
+        vec![
+            ("<unk>".to_string(), 0.0),
+            ("<s>".to_string(), 0.0),
+            ("</s>".to_string(), 0.0),
+            ("a".to_string(), -1.0),
+            ("b".to_string(), -1.0),
+            ("ab".to_string(), -1.5),
+        ]
+    }
+
+    #[test]
+    fn vocab_jaccard_on_overlapping_vocabs() {
+        let a = Unigram::from(sample_vocab(), Some(0));
+
+        let mut other_vocab = sample_vocab();
+        other_vocab.push(("c".to_string(), -1.0));
+        other_vocab.retain(|(token, _)| token != "ab");
+        let b = Unigram::from(other_vocab, Some(0));
+
+        // Shared: <unk>, <s>, </s>, a, b = 5. Union adds "ab" and "c" = 7.
+        assert!((a.vocab_jaccard(&b) - 5.0 / 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn unique_pieces_used_counts_distinct_ids() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let corpus = vec!["ab".to_string(), "ab".to_string(), "a".to_string()];
+
+        // "ab" always wins as a single piece (better score than "a"+"b"), so
+        // only the "ab" and "a" ids are ever produced.
+        assert_eq!(model.unique_pieces_used(&corpus), 2);
+    }
+
+    #[test]
+    fn verify_roundtrip_reports_only_real_mismatches() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let corpus = vec!["ab".to_string(), "z".to_string()];
+
+        let failures = model.verify_roundtrip(&corpus);
+
+        // "ab" round-trips fine; "z" has no vocab coverage, so it decodes
+        // through the unk token and doesn't reproduce the original text.
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].text, "z");
+    }
+
+    #[test]
+    fn assert_save_load_stable_passes_for_the_default_unk_id() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let corpus = vec!["ab".to_string(), "a".to_string(), "z".to_string()];
+
+        assert!(model.assert_save_load_stable(&corpus).is_ok());
+    }
+
+    #[test]
+    fn assert_save_load_stable_reports_a_non_default_unk_id() {
+        // `assert_save_load_stable` reloads assuming unk id 0 (the same
+        // convention `load_spm` uses), because `save` doesn't persist
+        // `unk_id` itself. A model whose unk token isn't at id 0 round-trips
+        // to a model with a *different* unk token, so this currently (and
+        // correctly) fails until `save`/load round-trip that special-id
+        // envelope too.
+        let mut vocab = sample_vocab();
+        vocab.push(("z".to_string(), -1.0));
+        let unk_id = vocab.iter().position(|(t, _)| t == "b").unwrap();
+        let model = Unigram::from(vocab, Some(unk_id));
+
+        let corpus = vec!["q".to_string()];
+        assert!(model.assert_save_load_stable(&corpus).is_err());
+    }
+
+    #[test]
+    fn from_with_special_tokens_resolves_custom_spellings() {
+        let vocab = vec![
+            ("<|unk|>".to_string(), 0.0),
+            ("<|startoftext|>".to_string(), 0.0),
+            ("<|endoftext|>".to_string(), 0.0),
+            ("a".to_string(), -1.0),
+        ];
+        let model = Unigram::from_with_special_tokens(
+            vocab,
+            Some("<|unk|>"),
+            Some("<|startoftext|>"),
+            Some("<|endoftext|>"),
+        )
+        .unwrap();
+
+        assert_eq!(model.unk_id(), Some(0));
+        assert_eq!(model.bos_id(), Some(1));
+        assert_eq!(model.eos_id(), Some(2));
+    }
+
+    #[test]
+    fn from_with_special_tokens_reports_a_missing_special_instead_of_panicking() {
+        let vocab = vec![("<s>".to_string(), 0.0), ("</s>".to_string(), 0.0)];
+        let result =
+            Unigram::from_with_special_tokens(vocab, Some("<unk>"), Some("<s>"), Some("</s>"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn from_accepts_a_vocab_with_no_bos_or_eos() {
+        let vocab = vec![("<unk>".to_string(), 0.0), ("a".to_string(), -1.0)];
+        let model = Unigram::from(vocab, Some(0));
+
+        assert_eq!(model.bos_id(), None);
+        assert_eq!(model.eos_id(), None);
+        assert_eq!(model.tokenize("a").unwrap()[0].value, "a");
+    }
+
+    #[test]
+    fn unigram_builder_builds_an_equivalent_model_to_from() {
+        let built = UnigramBuilder::new(sample_vocab())
+            .unk_id(Some(0))
+            .build()
+            .unwrap();
+        let from = Unigram::from(sample_vocab(), Some(0));
+
+        assert_eq!(built.vocab(), from.vocab());
+        assert_eq!(built.unk_id(), from.unk_id());
+        assert_eq!(built.bos_id(), from.bos_id());
+        assert_eq!(built.eos_id(), from.eos_id());
+    }
+
+    #[test]
+    fn unigram_builder_rejects_an_empty_token() {
+        let vocab = vec![("<unk>".to_string(), 0.0), ("".to_string(), -1.0)];
+        let result = UnigramBuilder::new(vocab).unk_id(Some(0)).build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unigram_builder_rejects_a_non_finite_score() {
+        let vocab = vec![("<unk>".to_string(), 0.0), ("a".to_string(), f64::NEG_INFINITY)];
+        let result = UnigramBuilder::new(vocab).unk_id(Some(0)).build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unigram_builder_rejects_an_out_of_range_id() {
+        let vocab = vec![("<unk>".to_string(), 0.0), ("a".to_string(), -1.0)];
+        let result = UnigramBuilder::new(vocab).unk_id(Some(5)).build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unigram_builder_threads_byte_fallback_through() {
+        let model = UnigramBuilder::new(byte_fallback_vocab())
+            .unk_id(Some(0))
+            .byte_fallback(true)
+            .build()
+            .unwrap();
+        let tokens = model.tokenize("\u{1F980}").unwrap();
+        assert_eq!(tokens.len(), "\u{1F980}".len());
+        assert_eq!(tokens[0].value, "<0xF0>");
+    }
+
+    #[test]
+    fn try_from_rejects_an_empty_token() {
+        let vocab = vec![("<unk>".to_string(), 0.0), ("".to_string(), -1.0)];
+        let result = Unigram::try_from(vocab, Some(0), None, None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn try_from_rejects_a_non_finite_score() {
+        let vocab = vec![("<unk>".to_string(), 0.0), ("a".to_string(), f64::NAN)];
+        let result = Unigram::try_from(vocab, Some(0), None, None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn try_from_rejects_an_out_of_range_unk_id() {
+        let vocab = vec![("<unk>".to_string(), 0.0), ("a".to_string(), -1.0)];
+        let result = Unigram::try_from(vocab, Some(2), None, None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn try_from_accepts_a_valid_vocab_and_ids() {
+        let vocab = vec![("<unk>".to_string(), 0.0), ("a".to_string(), -1.0)];
+        let model = Unigram::try_from(vocab, Some(0), None, None).unwrap();
+        assert_eq!(model.unk_id(), Some(0));
+    }
+
+    #[test]
+    fn with_piece_types_rejects_a_length_mismatch() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        assert!(model.with_piece_types(vec![PieceType::Normal]).is_err());
+    }
+
+    #[test]
+    fn from_defaults_every_piece_to_normal_except_the_unk_id() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        assert_eq!(model.piece_type(0), Some(PieceType::Unknown));
+        assert_eq!(model.piece_type(1), Some(PieceType::Normal));
+    }
+
+    #[test]
+    fn a_multi_char_control_piece_is_never_tokenized_as_unknown() {
+        let mut vocab = sample_vocab();
+        vocab.push(("<pad>".to_string(), 0.0));
+        let pad_id = vocab.len() - 1;
+        let model = Unigram::from(vocab, Some(0))
+            .with_piece_types({
+                let mut types = vec![PieceType::Normal; pad_id + 1];
+                types[0] = PieceType::Unknown;
+                types[pad_id] = PieceType::Control;
+                types
+            })
+            .unwrap();
+
+        let tokens = model.tokenize("<pad>").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].value, "<pad>");
+        assert_eq!(tokens[0].id, pad_id as u32);
+    }
+
+    /// A vocab with every `<0x00>`..`<0xFF>` byte piece, for byte-fallback
+    /// tests, plus the usual mandatory specials.
+    fn byte_fallback_vocab() -> Vec<(String, f64)> {
+        let mut vocab = vec![
+            ("<unk>".to_string(), 0.0),
+            ("<s>".to_string(), 0.0),
+            ("</s>".to_string(), 0.0),
+        ];
+        for byte in 0u16..=0xFF {
+            vocab.push((format!("<0x{:02X}>", byte), -1.0));
+        }
+        vocab
+    }
+
+    #[test]
+    fn byte_fallback_round_trips_an_unseen_emoji_through_its_bytes() {
+        let model = Unigram::from(byte_fallback_vocab(), Some(0)).with_byte_fallback(true);
+
+        let tokens = model.tokenize("🦀").unwrap();
+        let expected_bytes: Vec<u8> = "🦀".bytes().collect();
+        assert_eq!(tokens.len(), expected_bytes.len());
+        for (token, byte) in tokens.iter().zip(expected_bytes.iter()) {
+            assert_eq!(token.value, format!("<0x{:02X}>", byte));
+        }
+
+        let reassembled: Vec<u8> = tokens
+            .iter()
+            .map(|token| {
+                u8::from_str_radix(token.value.trim_start_matches("<0x").trim_end_matches('>'), 16)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(String::from_utf8(reassembled).unwrap(), "🦀");
+    }
+
+    #[test]
+    fn byte_fallback_is_unused_when_disabled() {
+        let model = Unigram::from(byte_fallback_vocab(), Some(0));
+        let tokens = model.tokenize("🦀").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].id, 0);
+    }
+
+    #[test]
+    fn unk_penalty_mode_scales_with_byte_length() {
+        // "語" is a 3-byte character that has no vocab coverage, so it is
+        // always tokenized as a single unk node.
+        let per_token = Unigram::from(sample_vocab(), Some(0));
+        let mut per_byte = Unigram::from(sample_vocab(), Some(0));
+        per_byte.set_unk_penalty_mode(UnkPenaltyMode::PerByte);
+
+        let mut token_lattice = Lattice::from("語");
+        per_token.populate_nodes(&mut token_lattice);
+        let mut byte_lattice = Lattice::from("語");
+        per_byte.populate_nodes(&mut byte_lattice);
+
+        let token_score = token_lattice.node(token_lattice.viterbi()[0]).score;
+        let byte_score = byte_lattice.node(byte_lattice.viterbi()[0]).score;
+
+        assert_eq!(token_score, -K_UNK_PENALTY);
+        assert_eq!(byte_score, -K_UNK_PENALTY * 3.0);
+    }
+
+    #[test]
+    fn a_larger_unk_penalty_favors_known_pieces_over_unk_fallback() {
+        // "x" and "y" aren't individually in the vocab, so tokenizing them
+        // apart costs two unk penalties, competing against the single known
+        // (but low-scoring) piece "xy".
+        let vocab = vec![("<unk>".to_string(), 0.0), ("xy".to_string(), -3.0)];
+
+        let mut lenient = Unigram::from(vocab.clone(), Some(0));
+        lenient.set_unk_penalty(1.0);
+        let lenient_tokens = lenient.tokenize("xy").unwrap();
+        assert_eq!(lenient_tokens.len(), 2);
+        assert!(lenient_tokens.iter().all(|t| t.id == 0));
+
+        // The default penalty (10.0) is steep enough that two unk nodes
+        // (-20.0) lose to the single known piece (-3.0).
+        let strict = Unigram::from(vocab, Some(0));
+        let strict_tokens = strict.tokenize("xy").unwrap();
+        assert_eq!(strict_tokens.len(), 1);
+        assert_eq!(strict_tokens[0].value, "xy");
+    }
+
+    #[cfg(feature = "unicode-script")]
+    #[test]
+    fn pieces_in_script_filters_by_unicode_script() {
+        use unicode_script::Script;
+
+        let mut vocab = sample_vocab();
+        vocab.push(("ひら".to_string(), -1.0));
+        vocab.push(("がな".to_string(), -1.0));
+        let model = Unigram::from(vocab, Some(0));
+
+        let hiragana: Vec<&str> = model
+            .pieces_in_script(Script::Hiragana)
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+
+        assert_eq!(hiragana, vec!["ひら", "がな"]);
+    }
+
+    #[cfg(feature = "unicode-script")]
+    #[test]
+    fn encode_fuse_unk_by_script_splits_at_script_boundary() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        // Neither "x" nor "語" is in the vocab, so both are unk. With script
+        // fusing on, the Latin run and the CJK run stay as two pieces.
+        let pieces = model.encode_fuse_unk("xx語語", true);
+        assert_eq!(pieces, vec!["xx".to_string(), "語語".to_string()]);
+
+        // Without script fusing, the whole unk run merges into one piece.
+        let fused = model.encode_fuse_unk("xx語語", false);
+        assert_eq!(fused, vec!["xx語語".to_string()]);
+    }
+
+    #[test]
+    fn unk_score_override_takes_precedence_over_penalty_mode() {
+        let mut model = Unigram::from(sample_vocab(), Some(0));
+        model.set_unk_score_override(Some(-2.5));
+
+        // "語" has no vocab coverage, so it's always a single unk node.
+        let mut lattice = Lattice::from("語");
+        model.populate_nodes(&mut lattice);
+
+        let score = lattice.node(lattice.viterbi()[0]).score;
+        assert_eq!(score, -2.5);
+    }
+
+    #[test]
+    fn encode_word_aligned_tags_pieces_with_their_word_index() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let aligned = model.encode_word_aligned(&["ab", "a"]);
+
+        assert_eq!(
+            aligned,
+            vec![("ab".to_string(), 0), ("a".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn encode_masked_falls_back_when_a_piece_is_forbidden() {
+        let mut vocab = sample_vocab();
+        vocab.push(("abcd".to_string(), -1.0));
+        vocab.push(("cd".to_string(), -1.0));
+        let model = Unigram::from(vocab, Some(0));
+
+        let abcd_id = model.token_to_id("abcd").unwrap();
+        assert_eq!(model.encode_masked("abcd", &std::collections::HashSet::new()), vec!["abcd".to_string()]);
+
+        let forbidden: std::collections::HashSet<u32> = std::iter::once(abcd_id).collect();
+        assert_eq!(
+            model.encode_masked("abcd", &forbidden),
+            vec!["ab".to_string(), "cd".to_string()]
+        );
+    }
+
+    #[test]
+    fn sampling_divergence_grows_as_alpha_shrinks() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let corpus: Vec<String> = std::iter::repeat("ab".to_string()).take(20).collect();
+
+        // A small alpha flattens the sampling distribution toward uniform
+        // over paths, pulling samples away from the Viterbi best path more
+        // often than a large alpha, which sharpens it toward Viterbi.
+        let low_alpha_divergence = model.sampling_divergence(&corpus, 0.1, 7);
+        let high_alpha_divergence = model.sampling_divergence(&corpus, 10.0, 7);
+
+        assert!(low_alpha_divergence > high_alpha_divergence);
+    }
+
+    #[test]
+    fn provably_dead_pieces_flags_dominated_multi_char_pieces() {
+        let vocab = vec![
+            ("<unk>".to_string(), 0.0),
+            ("<s>".to_string(), 0.0),
+            ("</s>".to_string(), 0.0),
+            ("a".to_string(), -1.0),
+            ("b".to_string(), -1.0),
+            ("x".to_string(), -1.0),
+            ("y".to_string(), -1.0),
+            ("ab".to_string(), -5.0), // worse than "a"+"b" (-2.0): dead.
+            ("xy".to_string(), -0.5), // better than "x"+"y" (-2.0): alive.
+        ];
+        let model = Unigram::from(vocab, Some(0));
+
+        let ab_id = model.token_to_id("ab").unwrap();
+        let xy_id = model.token_to_id("xy").unwrap();
+        let dead = model.provably_dead_pieces();
+
+        assert!(dead.contains(&ab_id));
+        assert!(!dead.contains(&xy_id));
+    }
+
+    #[test]
+    fn piece_idf_is_near_zero_for_a_piece_in_every_doc() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let corpus = vec!["ab".to_string(), "ab".to_string(), "aba".to_string()];
+
+        let idf = model.piece_idf(&corpus);
+
+        let ab_id = model.token_to_id("ab").unwrap();
+        // "ab" appears in all 3 docs: ln(3/3) == 0.0.
+        assert!((idf[&ab_id] - 0.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn canonicalize_specials_reorders_ids_but_keeps_tokenization() {
+        let mut model = Unigram::from(sample_vocab(), Some(0));
+        let before = model.tokenize("ab").unwrap();
+        let before_values: Vec<&str> = before.iter().map(|t| t.value.as_str()).collect();
+
+        model
+            .canonicalize_specials(&["<s>", "</s>", "<unk>"])
+            .unwrap();
+
+        // "<unk>" moved from id 0 to id 2; "<s>"/"</s>" now lead at 0/1.
+        assert_eq!(model.unk_id(), Some(2));
+        assert_eq!(model.bos_id(), Some(0));
+        assert_eq!(model.eos_id(), Some(1));
+
+        let after = model.tokenize("ab").unwrap();
+        let after_values: Vec<&str> = after.iter().map(|t| t.value.as_str()).collect();
+        assert_eq!(before_values, after_values);
+    }
+
+    #[test]
+    fn canonicalize_specials_errors_on_an_unknown_token() {
+        let mut model = Unigram::from(sample_vocab(), Some(0));
+        assert!(model.canonicalize_specials(&["<nonexistent>"]).is_err());
+    }
+
+    #[test]
+    fn encode_with_probs_is_near_one_for_an_unambiguous_span() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        // "a" has exactly one lattice node covering it, so it's the only
+        // possible path: its posterior probability should be ~1.0.
+        let probs = model.encode_with_probs("a");
+        assert_eq!(probs.len(), 1);
+        assert!((probs[0].1 - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn minimize_for_reproduces_the_same_tokenization() {
+        let mut vocab = sample_vocab();
+        vocab.push(("z".to_string(), -1.0)); // unrelated piece, never a candidate for "ab"
+        let model = Unigram::from(vocab, Some(0));
+
+        let minimized = model.minimize_for("ab");
+
+        assert!(minimized.get_vocab_size() < model.get_vocab_size());
+        assert_eq!(minimized.token_to_id("z"), None);
+        assert_eq!(
+            minimized.tokenize("ab").unwrap()[0].value,
+            model.tokenize("ab").unwrap()[0].value
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "empty token")]
+    fn from_rejects_an_empty_token() {
+        let mut vocab = sample_vocab();
+        vocab.push(("".to_string(), -1.0));
+        Unigram::from(vocab, Some(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "empty token at line 3")]
+    fn parse_spm_vocab_rejects_an_empty_token_with_its_line() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"<unk>\t0.0\n<s>\t0.0\n</s>\t0.0\n\t-1.0\n")
+            .unwrap();
+
+        let _ = parse_spm_vocab(file.path());
+    }
+
+    #[test]
+    fn parse_spm_vocab_keeps_a_tab_that_is_part_of_the_token() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"<unk>\t0.0\na\tb\t-2.0\n").unwrap();
+
+        let vocab = parse_spm_vocab(file.path()).unwrap();
+        assert_eq!(vocab[1], ("a\tb".to_string(), -2.0));
+    }
+
+    #[test]
+    fn parse_spm_vocab_accepts_whitespace_separated_columns() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"<unk> 0.0\na -1.0\n").unwrap();
+
+        let vocab = parse_spm_vocab(file.path()).unwrap();
+        assert_eq!(vocab, vec![("<unk>".to_string(), 0.0), ("a".to_string(), -1.0)]);
+    }
+
+    #[test]
+    fn load_spm_reports_a_missing_file_instead_of_panicking() {
+        let result = load_spm(Path::new("/nonexistent/path/to/a/vocab.txt"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn load_spm_reports_a_bad_score_instead_of_panicking() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"<unk>\t0.0\n<s>\t0.0\n</s>\t0.0\na\tnot-a-number\n")
+            .unwrap();
+
+        let err = load_spm(file.path()).unwrap_err();
+        assert!(err.to_string().contains("not-a-number"));
+    }
+
+    #[test]
+    fn save_spm_then_load_spm_round_trips_to_an_equal_model() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("<unk>\t0.0\n\u{2581}a\t-1.0\n\u{2581}b\t-1.5\n".as_bytes())
+            .unwrap();
+        let model = load_spm(file.path()).unwrap();
+
+        let roundtrip_file = NamedTempFile::new().unwrap();
+        model.save_spm(roundtrip_file.path()).unwrap();
+        let reloaded = load_spm(roundtrip_file.path()).unwrap();
+
+        assert_eq!(model, reloaded);
+    }
+
+    #[test]
+    fn normalize_for_spm_adds_a_dummy_prefix_and_escapes_whitespace() {
+        assert_eq!(
+            normalize_for_spm("Hello world"),
+            "\u{2581}Hello\u{2581}world"
+        );
+    }
+
+    #[test]
+    fn normalize_for_spm_collapses_extra_whitespace() {
+        assert_eq!(
+            normalize_for_spm("Hello   world"),
+            "\u{2581}Hello\u{2581}world"
+        );
+    }
+
+    #[test]
+    fn tokenize_after_normalize_for_spm_yields_space_marked_pieces() {
+        let vocab = vec![
+            ("<unk>".to_string(), 0.0),
+            ("\u{2581}Hello".to_string(), -1.0),
+            ("\u{2581}world".to_string(), -1.0),
+        ];
+        let model = Unigram::from(vocab, Some(0));
+
+        let tokens = model.tokenize(&normalize_for_spm("Hello world")).unwrap();
+        let pieces: Vec<String> = tokens.into_iter().map(|t| t.value).collect();
+        assert_eq!(
+            pieces,
+            vec!["\u{2581}Hello".to_string(), "\u{2581}world".to_string()]
+        );
+    }
+
+    #[test]
+    fn load_error_display_includes_the_variant_detail() {
+        let err = LoadError::InvalidLine(3);
+        assert!(format!("{}", err).contains('3'));
+    }
+
+    #[test]
+    fn load_error_source_does_not_cycle_back_to_itself() {
+        let err: Box<dyn std::error::Error> = Box::new(LoadError::InvalidLine(0));
+        assert!(std::error::Error::source(err.as_ref()).is_none());
+    }
+
+    #[test]
+    fn trie_is_not_built_for_id_only_access() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let before = model.trie_build_count();
+
+        assert_eq!(model.token_to_id("a"), Some(3));
+        assert_eq!(model.id_to_token(3), Some("a"));
+        assert_eq!(model.trie_build_count(), before);
+
+        model.tokenize("ab").unwrap();
+        assert_eq!(model.trie_build_count(), before + 1);
+    }
+
+    #[test]
+    fn encode_fast_matches_tokenize_pieces() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        for sentence in &["ab", "a", "b", "abab語", "語語"] {
+            let expected: Vec<String> = model
+                .tokenize(sentence)
+                .unwrap()
+                .into_iter()
+                .map(|t| t.value)
+                .collect();
+            assert_eq!(model.encode_fast(sentence), expected, "mismatch for {:?}", sentence);
+        }
+    }
+
+    #[cfg(feature = "unicode-segmentation")]
+    #[test]
+    fn encode_grapheme_aware_keeps_a_zwj_emoji_as_one_unk_piece() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        // Family emoji (man, ZWJ, woman, ZWJ, girl): a single grapheme
+        // cluster made of five codepoints joined by ZWJ, none of which are
+        // in the vocab.
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+
+        let unaware = model.encode_grapheme_aware(family, false);
+        assert_eq!(unaware.len(), 5);
+
+        let aware = model.encode_grapheme_aware(family, true);
+        assert_eq!(aware, vec![family.to_string()]);
+    }
+
+    #[test]
+    fn fertility_computes_average_pieces_per_word_per_language() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        // "ab" tokenizes as one piece; "ba" (not in vocab) splits into two.
+        let samples = [("short", "ab ab"), ("long", "ba ba")];
+        let fertility = model.fertility(&samples);
+
+        assert_eq!(fertility["short"], 1.0);
+        assert_eq!(fertility["long"], 2.0);
+    }
+
+    #[test]
+    fn encode_with_offsets_merges_fused_unk_offset_ranges() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        // "ab" is covered; "語語" is two adjacent unk chars that fuse into
+        // one piece spanning both.
+        let pieces = model.encode_with_offsets("ab語語");
+
+        assert_eq!(
+            pieces,
+            vec![
+                ("ab".to_string(), (0, 2)),
+                ("語語".to_string(), (2, 8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_reports_real_byte_offsets_for_multibyte_chars() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        // "xyz" is all unk (one byte each); "東" and "京" are unk 3-byte
+        // chars, so each token here is exactly one char.
+        let tokens = model.tokenize("xyz東京").unwrap();
+
+        let expected_offsets = [(0, 1), (1, 2), (2, 3), (3, 6), (6, 9)];
+        assert_eq!(tokens.len(), expected_offsets.len());
+        for (token, &offsets) in tokens.iter().zip(expected_offsets.iter()) {
+            assert_eq!(token.offsets, offsets);
+        }
+        assert_eq!(&"xyz東京"[tokens[3].offsets.0..tokens[3].offsets.1], "東");
+        assert_eq!(&"xyz東京"[tokens[4].offsets.0..tokens[4].offsets.1], "京");
+    }
+
+    #[test]
+    fn shared_trie_across_instances_produces_independent_correct_tokenizations() {
+        // Ids matching `sample_vocab()`'s own sequential assignment
+        // (<unk>=0, <s>=1, </s>=2, a=3, b=4, ab=5), plus "c" at the id it
+        // gets once appended to `sample_vocab()` for model_b below, so each
+        // model's `with_shared_trie` validation (which checks ids, not just
+        // recognized strings) accepts it.
+        let mut base_trie = Trie::new();
+        for (id, token) in ["<unk>", "<s>", "</s>"].iter().enumerate() {
+            base_trie.push_with_value(token.chars(), id as u32);
+        }
+        for (id, token) in ["a", "b", "ab", "c"].iter().enumerate() {
+            base_trie.push_with_value(token.chars(), id as u32 + 3);
+        }
+        let shared = std::sync::Arc::new(base_trie);
+
+        let model_a = Unigram::from(sample_vocab(), Some(0))
+            .with_shared_trie(shared.clone())
+            .unwrap();
+        let mut vocab_b = sample_vocab();
+        vocab_b.push(("c".to_string(), -1.0));
+        let model_b = Unigram::from(vocab_b, Some(0))
+            .with_shared_trie(shared)
+            .unwrap();
+
+        assert_eq!(model_a.tokenize("ab").unwrap()[0].value, "ab");
+        assert_eq!(model_b.tokenize("c").unwrap()[0].value, "c");
+
+        // "c" isn't in model_a's own vocab, even though the shared trie
+        // recognizes it: model_a must still fall back to unk rather than
+        // mistakenly emitting a token id from model_b's vocab.
+        let fallback = model_a.tokenize("c").unwrap();
+        assert_eq!(fallback[0].id, model_a.unk_id().unwrap() as u32);
+    }
+
+    #[test]
+    fn with_shared_trie_rejects_a_trie_missing_a_vocab_token() {
+        let mut base_trie = Trie::new();
+        base_trie.push_with_value("a".chars(), 3);
+        base_trie.push_with_value("b".chars(), 4);
+        // Deliberately missing "ab", which is in `sample_vocab()`.
+
+        let result = Unigram::from(sample_vocab(), Some(0))
+            .with_shared_trie(std::sync::Arc::new(base_trie));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn with_shared_trie_rejects_a_trie_whose_id_for_a_vocab_token_disagrees() {
+        let mut base_trie = Trie::new();
+        base_trie.push_with_value("a".chars(), 3);
+        base_trie.push_with_value("b".chars(), 4);
+        // Wrong id for "ab": `sample_vocab()` assigns it id 5, not 99.
+        base_trie.push_with_value("ab".chars(), 99);
+
+        let result = Unigram::from(sample_vocab(), Some(0))
+            .with_shared_trie(std::sync::Arc::new(base_trie));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn distribution_overlap_is_low_for_clearly_different_corpora() {
+        let mut vocab = sample_vocab();
+        vocab.push(("z".to_string(), -1.0));
+        let model = Unigram::from(vocab, Some(0));
+
+        let corpus_a: Vec<String> = std::iter::repeat("ab".to_string()).take(10).collect();
+        let corpus_b: Vec<String> = std::iter::repeat("z".to_string()).take(10).collect();
+
+        assert_eq!(model.distribution_overlap(&corpus_a, &corpus_b), 0.0);
+        assert_eq!(model.distribution_overlap(&corpus_a, &corpus_a), 1.0);
+    }
+
+    #[test]
+    fn encode_conll_emits_piece_id_and_byte_columns() {
+        let mut vocab = sample_vocab();
+        vocab.push(("cd".to_string(), -1.0));
+        let model = Unigram::from(vocab, Some(0));
+
+        let tsv = model.encode_conll("abcd");
+
+        let ab_id = model.token_to_id("ab").unwrap();
+        let cd_id = model.token_to_id("cd").unwrap();
+        let expected = format!("ab\t{}\t0\t2\ncd\t{}\t2\t4\n", ab_id, cd_id);
+        assert_eq!(tsv, expected);
+    }
+
+    #[test]
+    fn alphabet_collects_every_single_char_piece() {
+        let vocab = vec![
+            ("<unk>".to_string(), 0.0),
+            ("<s>".to_string(), 0.0),
+            ("</s>".to_string(), 0.0),
+            ("a".to_string(), -1.0),
+            ("b".to_string(), -1.0),
+            ("c".to_string(), -1.0),
+            ("d".to_string(), -1.0),
+            ("ab".to_string(), -1.5), // multi-char: not part of the alphabet
+        ];
+        let model = Unigram::from(vocab, Some(0));
+
+        let alphabet = model.alphabet();
+        let expected: std::collections::HashSet<char> = ['a', 'b', 'c', 'd'].iter().copied().collect();
+        assert_eq!(alphabet, expected);
+    }
+
+    #[test]
+    fn encode_into_matches_tokenize_and_reuses_the_buffer() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let mut out = Vec::with_capacity(16);
+        out.push("leftover".to_string());
+
+        model.encode_into("ab", false, &mut out);
+
+        let expected: Vec<String> = model
+            .tokenize("ab")
+            .unwrap()
+            .into_iter()
+            .map(|t| t.value)
+            .collect();
+        assert_eq!(out, expected);
+        assert!(out.capacity() >= 16);
+    }
+
+    #[test]
+    fn encode_into_fuses_unk_when_requested() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let mut out = vec![];
+
+        model.encode_into("語語", true, &mut out);
+        assert_eq!(out, vec!["語語".to_string()]);
+
+        model.encode_into("語語", false, &mut out);
+        assert_eq!(out.len(), 2);
+    }
+
+    #[test]
+    fn encode_into_with_workspace_matches_encode_into() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let mut ws = EncodeWorkspace::new();
+        let mut out = vec![];
+        let mut expected = vec![];
+
+        for sentence in ["ab", "語語", "", " ", "abcab"] {
+            for fuse_unk in [false, true] {
+                model.encode_into_with_workspace(sentence, &mut ws, fuse_unk, &mut out);
+                model.encode_into(sentence, fuse_unk, &mut expected);
+                assert_eq!(out, expected, "sentence={:?} fuse_unk={}", sentence, fuse_unk);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_into_with_workspace_reuses_its_buffers_across_calls() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let mut ws = EncodeWorkspace::new();
+        let mut out = vec![];
+
+        model.encode_into_with_workspace("abcab", &mut ws, false, &mut out);
+        let capacities = (ws.chars.capacity(), ws.best_score_at.capacity());
+        assert!(capacities.0 >= 5 && capacities.1 >= 6);
+
+        model.encode_into_with_workspace("ab", &mut ws, false, &mut out);
+        assert_eq!(out, vec!["ab".to_string()]);
+        // A shorter call doesn't need to grow the buffers back down.
+        assert!(ws.chars.capacity() >= capacities.0);
+    }
+
+    #[test]
+    fn encode_batch_matches_mapping_encode_into_serially() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let sentences = ["ab", "abc", "語語", "d"];
+
+        let batched = model.encode_batch(&sentences, true);
+
+        let expected: Vec<Vec<String>> = sentences
+            .iter()
+            .map(|sentence| {
+                let mut out = Vec::new();
+                model.encode_into(sentence, true, &mut out);
+                out
+            })
+            .collect();
+        assert_eq!(batched, expected);
+    }
+
+    #[test]
+    fn build_lattice_viterbi_matches_tokenize() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let lattice = model.build_lattice("ab");
+        let pieces: Vec<String> = lattice
+            .viterbi()
+            .into_iter()
+            .map(|id| lattice.piece(id))
+            .collect();
+
+        let tokens = model.tokenize("ab").unwrap();
+        let expected: Vec<String> = tokens.into_iter().map(|t| t.value).collect();
+        assert_eq!(pieces, expected);
+    }
+
+    #[test]
+    fn encode_reader_matches_encode_into_called_per_line() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let text = "ab\nabc\nd\n";
+
+        let streamed: Vec<String> = model
+            .encode_reader(std::io::Cursor::new(text), true)
+            .collect::<Result<_>>()
+            .unwrap();
+
+        let mut expected = Vec::new();
+        for line in text.lines() {
+            let mut out = Vec::new();
+            model.encode_into(line, true, &mut out);
+            expected.extend(out);
+        }
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn perturbation_sensitivity_is_higher_for_an_ambiguity_prone_vocab() {
+        // Single chars plus the two-char "ab": a perturbation can only
+        // disturb that one short piece, so the tokenization stays stable.
+        let stable = Unigram::from(sample_vocab(), Some(0));
+
+        // A long, greedily-preferred multi-char piece: perturbing any char
+        // inside "abcd" breaks the match and reflows the whole span into
+        // single chars, a much larger edit-distance hit.
+        let mut unstable_vocab = sample_vocab();
+        unstable_vocab.push(("abcd".to_string(), -0.1));
+        unstable_vocab.push(("c".to_string(), -1.0));
+        unstable_vocab.push(("d".to_string(), -1.0));
+        let unstable = Unigram::from(unstable_vocab, Some(0));
+
+        let stable_score = stable.perturbation_sensitivity("ab");
+        let unstable_score = unstable.perturbation_sensitivity("abcd");
+
+        assert!(unstable_score > stable_score);
+    }
+
+    #[test]
+    fn encode_with_fallback_covers_a_span_the_primary_cannot() {
+        let primary = Unigram::from(sample_vocab(), Some(0)); // only covers "a", "b", "ab"
+        let mut fallback_vocab = sample_vocab();
+        fallback_vocab.push(("z".to_string(), -1.0));
+        let fallback = Unigram::from(fallback_vocab, Some(0));
+
+        // "z" is unk for the primary but covered by the fallback.
+        let pieces = primary.encode_with_fallback("abz", &fallback);
+        assert_eq!(pieces, vec!["ab".to_string(), "z".to_string()]);
+    }
+
+    #[test]
+    fn compare_normalizations_differ_on_a_full_width_letter() {
+        // U+FF21 FULLWIDTH LATIN CAPITAL LETTER A has a *compatibility*
+        // decomposition to ASCII "A", not a canonical one, so NFKC folds it
+        // to "A" while NFC leaves the full-width form as is.
+        let mut vocab = sample_vocab();
+        vocab.push(("A".to_string(), -1.0));
+        let model = Unigram::from(vocab, Some(0));
+
+        let results = model.compare_normalizations(
+            "\u{FF21}",
+            &[Normalization::Nfc, Normalization::Nfkc],
+        );
+
+        assert_eq!(
+            results,
+            vec![
+                (Normalization::Nfc, vec!["\u{FF21}".to_string()]),
+                (Normalization::Nfkc, vec!["A".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn load_trie_bytes_skips_rebuilding_and_rejects_a_mismatched_fingerprint() {
+        let source = Unigram::from(sample_vocab(), Some(0));
+        let bytes = source.trie_to_bytes().unwrap();
+
+        let mut target = Unigram::from(sample_vocab(), Some(0));
+        let before = target.trie_build_count();
+        target.load_trie_bytes(&bytes).unwrap();
+        assert_eq!(target.trie_build_count(), before);
+        assert_eq!(target.tokenize("ab").unwrap()[0].value, "ab");
+
+        let mut other_vocab = sample_vocab();
+        other_vocab.push(("c".to_string(), -1.0));
+        let mut mismatched = Unigram::from(other_vocab, Some(0));
+        assert!(mismatched.load_trie_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn nbest_with_scores_matches_hand_computed_scores() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        // Only two segmentations of "ab" exist: "ab" (-1.5) and "a"+"b" (-2.0).
+        let nbest = model.nbest_with_scores("ab", 2);
+
+        assert_eq!(
+            nbest,
+            vec![
+                (vec!["ab".to_string()], -1.5),
+                (vec!["a".to_string(), "b".to_string()], -2.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn encode_nbest_returns_the_top_segmentations_without_scores() {
+        let vocab = vec![
+            ("<unk>".to_string(), 0.0),
+            ("<s>".to_string(), 0.0),
+            ("</s>".to_string(), 0.0),
+            ("a".to_string(), -1.0),
+            ("b".to_string(), -1.0),
+            ("c".to_string(), -1.0),
+            ("d".to_string(), -1.0),
+            ("ab".to_string(), -1.0),
+            ("cd".to_string(), -1.0),
+            ("abcd".to_string(), -1.2),
+        ];
+        let model = Unigram::from(vocab, Some(0));
+
+        let nbest = model.encode_nbest("abcd", 2);
+
+        assert!(nbest.contains(&vec!["abcd".to_string()]));
+        assert!(nbest.contains(&vec!["ab".to_string(), "cd".to_string()]));
+        assert_eq!(nbest.len(), 2);
+    }
+
+    #[test]
+    fn encode_nbest_returns_fewer_than_n_when_fewer_segmentations_exist() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        // Only two segmentations of "ab" exist: "ab" and "a"+"b".
+        let nbest = model.encode_nbest("ab", 5);
+
+        assert_eq!(nbest.len(), 2);
+    }
+
+    #[test]
+    fn with_bias_can_flip_the_winning_segmentation() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        // Unbiased, "ab" (-1.5) beats "a"+"b" (-2.0).
+        assert_eq!(model.tokenize("ab").unwrap()[0].value, "ab");
+
+        let ab_id = model.token_to_id("ab").unwrap() as usize;
+        let mut bias = vec![0.0; model.get_vocab_size()];
+        bias[ab_id] = -5.0; // now "ab" scores -6.5, well below "a"+"b".
+
+        let biased = model.with_bias(&bias);
+        let tokens = biased.tokenize("ab").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].value, "a");
+        assert_eq!(tokens[1].value, "b");
+    }
+
+    #[test]
+    fn is_ambiguous_detects_tied_best_paths() {
+        // "a"+"b" (-2.0) ties with "ab" (-2.0): ambiguous.
+        let tied_vocab = vec![
+            ("<unk>".to_string(), 0.0),
+            ("<s>".to_string(), 0.0),
+            ("</s>".to_string(), 0.0),
+            ("a".to_string(), -1.0),
+            ("b".to_string(), -1.0),
+            ("ab".to_string(), -2.0),
+        ];
+        let ambiguous_model = Unigram::from(tied_vocab, Some(0));
+        assert!(ambiguous_model.is_ambiguous("ab"));
+
+        // "ab" (-1.5) strictly beats "a"+"b" (-2.0): unambiguous.
+        let unambiguous_model = Unigram::from(sample_vocab(), Some(0));
+        assert!(!unambiguous_model.is_ambiguous("ab"));
+    }
+
+    #[test]
+    fn encode_ranges_reconstructs_same_pieces_as_tokenize() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let sentence = "ab語";
+
+        let tokens = model.tokenize(sentence).unwrap();
+        let ranges = model.encode_ranges(sentence, false);
+
+        assert_eq!(ranges.len(), tokens.len());
+        for (range, token) in ranges.iter().zip(tokens.iter()) {
+            assert_eq!(&sentence[range.0..range.1], token.value);
+        }
+    }
+
+    #[test]
+    fn encode_ranges_fuses_adjacent_unk_ranges() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let sentence = "語語";
+
+        // Both chars are unk and adjacent; fused they cover the whole string.
+        assert_eq!(
+            model.encode_ranges(sentence, true),
+            vec![(0, sentence.len())]
+        );
+        assert_eq!(model.encode_ranges(sentence, false).len(), 2);
+    }
+
+    #[test]
+    fn char_coverage_splits_covered_and_uncovered_chars() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let (covered, uncovered) = model.char_coverage(&['a', 'b', 'z']);
+
+        assert_eq!(covered, vec!['a', 'b']);
+        assert_eq!(uncovered, vec!['z']);
+    }
+
+    #[test]
+    fn missing_pieces_for_coverage_reports_uncovered_chars() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let corpus = vec!["ab".to_string(), "az".to_string()];
+
+        // "z" has no vocab entry; "a" and "b" do.
+        assert_eq!(
+            model.missing_pieces_for_coverage(&corpus),
+            vec!["z".to_string()]
+        );
+    }
+
+    #[test]
+    fn corpus_nll_decreases_when_a_helpful_piece_is_added() {
+        let corpus = vec!["ab".to_string(), "ab".to_string()];
+
+        let without_ab = Unigram::from(
+            vec![
+                ("<unk>".to_string(), 0.0),
+                ("<s>".to_string(), 0.0),
+                ("</s>".to_string(), 0.0),
+                ("a".to_string(), -1.0),
+                ("b".to_string(), -1.0),
+            ],
+            Some(0),
+        );
+        let with_ab = Unigram::from(sample_vocab(), Some(0)); // adds "ab" at -1.5
+
+        assert!(with_ab.corpus_nll(&corpus) < without_ab.corpus_nll(&corpus));
+    }
+
+    #[test]
+    fn encode_segments_looks_up_piece_by_byte_offset() {
+        let mut vocab = sample_vocab();
+        vocab.push(("cd".to_string(), -1.0));
+        vocab.push(("x".to_string(), -1.0));
+        let model = Unigram::from(vocab, Some(0));
+
+        // "ab" (0..2), "cd" (2..4), then "x", "x" (4..5, 5..6).
+        let segments = model.encode_segments("abcdxx");
+
+        assert_eq!(segments.piece_at_byte(0), Some(0));
+        assert_eq!(segments.piece_at_byte(1), Some(0));
+        assert_eq!(segments.piece_at_byte(2), Some(1));
+        assert_eq!(segments.piece_at_byte(4), Some(2));
+        assert_eq!(segments.piece_at_byte(5), Some(3));
+        assert_eq!(segments.piece_at_byte(6), None);
+    }
+
+    #[test]
+    fn encode_min_score_excludes_rare_pieces() {
+        let vocab = vec![
+            ("<unk>".to_string(), 0.0),
+            ("<s>".to_string(), 0.0),
+            ("</s>".to_string(), 0.0),
+            ("a".to_string(), -0.5),
+            ("b".to_string(), -0.5),
+            ("c".to_string(), -0.5),
+            ("abc".to_string(), -1.0),
+        ];
+        let model = Unigram::from(vocab, Some(0));
+
+        // "abc" as one piece (-1.0) beats "a"+"b"+"c" (-1.5), so it wins by default.
+        assert_eq!(model.encode_min_score("abc", f64::NEG_INFINITY), vec!["abc"]);
+
+        // Forbidding pieces scoring below -0.7 rules "abc" out, falling back
+        // to the individually-scored characters.
+        assert_eq!(
+            model.encode_min_score("abc", -0.7),
+            vec!["a".to_string(), "b".to_string(), "c".to_string()]
+        );
+    }
+
+    #[test]
+    fn sample_encode_is_deterministic_given_the_same_seed() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let first = model.sample_encode("ab", 0.5, 42);
+        let second = model.sample_encode("ab", 0.5, 42);
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn sample_encode_matches_sample_encode_detailed_pieces() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let pieces = model.sample_encode("ab", 0.5, 7);
+        let detailed: Vec<String> = model
+            .sample_encode_detailed("ab", 0.5, 7)
+            .into_iter()
+            .map(|encoded| encoded.piece)
+            .collect();
+
+        assert_eq!(pieces, detailed);
+    }
+
+    #[test]
+    fn sample_encode_converges_to_viterbi_at_high_alpha() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        // At a high alpha the sampling distribution sharpens towards the
+        // single best-scoring path, so the most frequent sample across many
+        // seeds should match plain Viterbi ("ab", not "a"+"b").
+        let viterbi: Vec<String> = model
+            .tokenize("ab")
+            .unwrap()
+            .into_iter()
+            .map(|token| token.value)
+            .collect();
+
+        let mut viterbi_count = 0;
+        let trials = 200;
+        for seed in 0..trials {
+            if model.sample_encode("ab", 20.0, seed) == viterbi {
+                viterbi_count += 1;
+            }
+        }
+
+        assert!(
+            viterbi_count as f64 / trials as f64 > 0.9,
+            "expected the Viterbi segmentation to dominate at a high alpha, got {}/{}",
+            viterbi_count,
+            trials
+        );
+    }
+
+    #[test]
+    fn sample_encode_detailed_ids_and_offsets_match_pieces() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let pieces = model.sample_encode_detailed("ab", 0.5, 42);
+
+        let mut consumed = 0;
+        for piece in &pieces {
+            assert_eq!(model.id_to_token(piece.id), Some(piece.piece.as_str()));
+            assert_eq!(piece.offsets, (consumed, consumed + piece.piece.len()));
+            consumed += piece.piece.len();
+        }
+        assert_eq!(consumed, "ab".len());
+    }
+
+    #[test]
+    fn merge_adjacent_recovers_a_known_vocab_piece() {
+        let mut vocab = sample_vocab();
+        vocab.push(("cd".to_string(), -1.0));
+        vocab.push(("abcd".to_string(), -1.0));
+        let model = Unigram::from(vocab, Some(0));
+
+        let pieces = vec!["ab".to_string(), "cd".to_string()];
+        assert_eq!(model.merge_adjacent(pieces), vec!["abcd".to_string()]);
+    }
+
+    #[test]
+    fn load_sharded_assigns_contiguous_ids_across_shards() {
+        let mut shard_a = NamedTempFile::new().unwrap();
+        shard_a
+            .write_all(b"<unk>\t0.0\n<s>\t0.0\n</s>\t0.0\na\t-1.0\n")
+            .unwrap();
+
+        let mut shard_b = NamedTempFile::new().unwrap();
+        shard_b.write_all(b"b\t-1.0\nab\t-1.5\n").unwrap();
+
+        let model = load_sharded(
+            &[shard_a.path(), shard_b.path()],
+            SpecialTokens {
+                unk_token: "<unk>".to_string(),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(model.get_vocab_size(), 6);
+        for (i, token) in ["<unk>", "<s>", "</s>", "a", "b", "ab"].iter().enumerate() {
+            assert_eq!(model.token_to_id(token), Some(i as u32));
+        }
+        assert_eq!(model.tokenize("ab").unwrap()[0].value, "ab");
+    }
+
+    #[test]
+    fn score_stats_excludes_special_tokens() {
+        // Non-special scores are -1.0, -2.0, -3.0: mean -2.0, median -2.0.
+        let vocab = vec![
+            ("<unk>".to_string(), -100.0),
+            ("<s>".to_string(), -100.0),
+            ("</s>".to_string(), -100.0),
+            ("a".to_string(), -1.0),
+            ("b".to_string(), -2.0),
+            ("c".to_string(), -3.0),
+        ];
+        let model = Unigram::from(vocab, Some(0));
+
+        let stats = model.score_stats();
+        assert_eq!(stats.min, -3.0);
+        assert_eq!(stats.max, -1.0);
+        assert_eq!(stats.mean, -2.0);
+        assert_eq!(stats.p50, -2.0);
+    }
+
+    #[test]
+    fn encode_ids_matches_mapping_tokenize_through_token_to_id() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let sentence = "aab";
+
+        let ids = model.encode_ids(sentence);
+
+        let expected: Vec<u32> = model
+            .tokenize(sentence)
+            .unwrap()
+            .into_iter()
+            .map(|token| model.token_to_id(&token.value).unwrap())
+            .collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn decode_stream_yields_a_chunk_per_id() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let ids = model.encode_ids("ab");
+
+        let chunks: Vec<String> = model.decode_stream(ids.into_iter()).collect();
+        assert_eq!(chunks, vec!["ab".to_string()]);
+    }
+
+    #[test]
+    fn decode_replaces_the_space_marker_back_into_a_literal_space() {
+        let mut vocab = sample_vocab();
+        vocab.push(("\u{2581}a".to_string(), -1.0));
+        let space_marked_id = (vocab.len() - 1) as u32;
+        let model = Unigram::from(vocab, Some(0));
+
+        assert_eq!(model.decode(&[space_marked_id]), " a");
+    }
+
+    #[test]
+    fn decode_reassembles_byte_fallback_pieces_into_the_original_bytes() {
+        let model = Unigram::from(byte_fallback_vocab(), Some(0)).with_byte_fallback(true);
+        let ids = model.encode_ids("🦀");
+
+        assert_eq!(model.decode(&ids), "🦀");
+    }
+
+    #[test]
+    fn decode_stream_holds_back_a_byte_fallback_run_until_it_completes() {
+        let model = Unigram::from(byte_fallback_vocab(), Some(0)).with_byte_fallback(true);
+        let crab_ids = model.encode_ids("🦀");
+        let unk_id = model.unk_id().unwrap() as u32;
+
+        let mut ids = crab_ids.clone();
+        ids.push(unk_id);
+        ids.extend(crab_ids);
+
+        let chunks: Vec<String> = model.decode_stream(ids.into_iter()).collect();
+        assert_eq!(
+            chunks,
+            vec!["🦀".to_string(), "<unk>".to_string(), "🦀".to_string()]
+        );
+    }
+
+    #[test]
+    fn scores_slice_matches_score_of_for_every_id() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let slice = model.scores_slice();
+        assert_eq!(slice.len(), model.get_vocab_size());
+        for id in 0..model.get_vocab_size() as u32 {
+            assert_eq!(Some(slice[id as usize]), model.score_of(id));
+        }
+    }
+
+    #[test]
+    fn token_score_matches_score_of_its_id() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let id = model.token_to_id("ab").unwrap();
+
+        assert_eq!(model.token_score("ab"), model.score_of(id));
+        assert_eq!(model.token_score("ab"), Some(-1.5));
+    }
+
+    #[test]
+    fn token_score_is_none_for_a_token_outside_the_vocab() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        assert_eq!(model.token_score("nope"), None);
+    }
+
+    #[test]
+    fn check_length_rejects_input_over_a_synthetic_cap() {
+        assert!(check_length(3, 5).is_ok());
+
+        let err = check_length(6, 5).unwrap_err();
+        match err {
+            EncodeError::InputTooLong { len, max } => {
+                assert_eq!(len, 6);
+                assert_eq!(max, 5);
+            }
+            other => panic!("expected InputTooLong, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn add_tokens_assigns_the_next_id_and_makes_the_token_findable() {
+        let mut model = Unigram::from(sample_vocab(), Some(0));
+        let next_id = model.get_vocab_size() as u32;
+
+        let ids = model.add_tokens(&[("<pad>".to_string(), 0.0)]).unwrap();
+
+        assert_eq!(ids, vec![next_id]);
+        assert_eq!(model.token_to_id("<pad>"), Some(next_id));
+        assert_eq!(model.get_vocab_size(), sample_vocab().len() + 1);
+        assert_eq!(model.tokenize("<pad>").unwrap()[0].id, next_id);
+    }
+
+    #[test]
+    fn add_tokens_returns_the_existing_id_for_a_duplicate_instead_of_adding_it_twice() {
+        let mut model = Unigram::from(sample_vocab(), Some(0));
+        let a_id = model.token_to_id("a").unwrap();
+
+        let ids = model.add_tokens(&[("a".to_string(), -5.0)]).unwrap();
+
+        assert_eq!(ids, vec![a_id]);
+        assert_eq!(model.get_vocab_size(), sample_vocab().len());
+        // The original score is untouched; a duplicate is a no-op, not an
+        // update.
+        assert_eq!(model.score_of(a_id), Some(-1.0));
+    }
+
+    #[test]
+    fn add_tokens_keeps_bos_eos_unk_ids_stable() {
+        let mut model = Unigram::from(sample_vocab(), Some(0));
+        let (unk, bos, eos) = (model.unk_id(), model.bos_id(), model.eos_id());
+
+        model.add_tokens(&[("<pad>".to_string(), 0.0)]).unwrap();
+
+        assert_eq!(model.unk_id(), unk);
+        assert_eq!(model.bos_id(), bos);
+        assert_eq!(model.eos_id(), eos);
+    }
+
+    #[test]
+    fn add_tokens_rejects_a_non_finite_score() {
+        let mut model = Unigram::from(sample_vocab(), Some(0));
+        assert!(model
+            .add_tokens(&[("<pad>".to_string(), f64::NAN)])
+            .is_err());
+    }
+
+    #[test]
+    fn try_repair_drops_duplicates_and_rebuilds_lookup() {
+        // `Unigram::from`/`try_from` reject a duplicate token outright (see
+        // `try_from_rejects_a_duplicate_token`), so the only way a model
+        // actually ends up with one is the direct field manipulation
+        // `try_repair`'s own doc comment calls out; simulate that here
+        // instead of going through `from`.
+        let mut model = Unigram::from(sample_vocab(), Some(0));
+        model.vocab.push(("a".to_string(), -9.0)); // duplicate of an earlier entry
+
+        let report = model.try_repair();
+
+        assert_eq!(report.duplicate_tokens_dropped, vec!["a".to_string()]);
+        assert_eq!(model.get_vocab_size(), sample_vocab().len());
+        assert!(model.token_to_id("a").is_some());
+        // The lookup and trie should both reflect the repaired vocab.
+        assert_eq!(model.tokenize("ab").unwrap()[0].value, "ab");
+    }
+
+    #[test]
+    fn prune_keeps_specials_and_the_highest_scoring_pieces() {
+        let mut vocab = sample_vocab();
+        vocab.push(("c".to_string(), -5.0));
+        vocab.push(("d".to_string(), -4.0));
+        vocab.push(("e".to_string(), -3.0));
+        let mut model = Unigram::from(vocab, Some(0));
+        assert_eq!(model.get_vocab_size(), 9);
+
+        model.prune(6);
+
+        assert_eq!(model.get_vocab_size(), 6);
+        // Specials always survive.
+        assert!(model.token_to_id("<unk>").is_some());
+        assert!(model.token_to_id("<s>").is_some());
+        assert!(model.token_to_id("</s>").is_some());
+        // The three highest-scoring non-special pieces (a: -1.0, b: -1.0,
+        // ab: -1.5) survive; the three lowest (c/d/e) are dropped.
+        assert!(model.token_to_id("a").is_some());
+        assert!(model.token_to_id("b").is_some());
+        assert!(model.token_to_id("ab").is_some());
+        assert!(model.token_to_id("c").is_none());
+        assert!(model.token_to_id("d").is_none());
+        assert!(model.token_to_id("e").is_none());
+        // The lookup and trie still agree after reindexing.
+        assert_eq!(model.tokenize("ab").unwrap()[0].value, "ab");
+    }
+
+    #[test]
+    fn prune_is_a_no_op_when_the_vocab_already_fits() {
+        let mut model = Unigram::from(sample_vocab(), Some(0));
+        let before = model.get_vocab_size();
+
+        model.prune(100);
+
+        assert_eq!(model.get_vocab_size(), before);
+    }
+
+    #[test]
+    fn tokenize_with_unk_behavior_passthrough_matches_tokenize() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let tokens = model
+            .tokenize_with_unk_behavior("z", UnkBehavior::Passthrough)
+            .unwrap();
+
+        assert_eq!(tokens, model.tokenize("z").unwrap());
+        assert_eq!(tokens[0].value, "z");
+    }
+
+    #[test]
+    fn tokenize_with_unk_behavior_symbol_replaces_the_unk_surface_text() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let tokens = model
+            .tokenize_with_unk_behavior("z", UnkBehavior::Symbol)
+            .unwrap();
+
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].id, 0); // unk_id
+        assert_eq!(tokens[0].value, "<unk>");
+        assert_eq!(tokens[0].offsets, (0, 1));
+    }
+
+    #[test]
+    fn tokenize_with_unk_behavior_bytes_splits_unk_into_byte_pieces() {
+        let mut vocab = sample_vocab();
+        vocab.push(("<0x7A>".to_string(), -3.0)); // 'z' is 0x7A in UTF-8
+        let model = Unigram::from(vocab, Some(0)); // byte_fallback stays off
+
+        // Passthrough is what plain `tokenize` returns when byte_fallback
+        // is off: one literal unk token, not the byte piece.
+        assert_eq!(model.tokenize("z").unwrap()[0].value, "z");
+
+        let tokens = model
+            .tokenize_with_unk_behavior("z", UnkBehavior::Bytes)
+            .unwrap();
+
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].value, "<0x7A>");
+        assert_eq!(tokens[0].offsets, (0, 1));
+    }
+
+    #[test]
+    fn tokenize_with_unk_behavior_bytes_falls_back_to_the_unk_id_without_a_vocab_entry() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let tokens = model
+            .tokenize_with_unk_behavior("z", UnkBehavior::Bytes)
+            .unwrap();
+
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].value, "<0x7A>");
+        assert_eq!(tokens[0].id, 0); // unk_id: no <0x7A> vocab entry to resolve to
+    }
+
+    #[test]
+    fn unk_id_bos_id_eos_id_report_their_position_in_the_doc_vocab() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        assert_eq!(model.unk_id(), Some(0));
+        assert_eq!(model.bos_id(), Some(1));
+        assert_eq!(model.eos_id(), Some(2));
+    }
+
+    #[test]
+    fn is_special_is_true_only_for_unk_bos_and_eos_ids() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        assert!(model.is_special(0)); // <unk>
+        assert!(model.is_special(1)); // <s>
+        assert!(model.is_special(2)); // </s>
+        assert!(!model.is_special(3)); // "a"
+    }
+
+    #[test]
+    fn try_from_rejects_a_duplicate_token() {
+        let mut vocab = sample_vocab();
+        vocab.push(("a".to_string(), -9.0)); // duplicate of the earlier "a"
+
+        let err = Unigram::try_from(vocab, Some(0), None, None).unwrap_err();
+
+        assert!(matches!(
+            err,
+            UnigramError::DuplicateToken {
+                token,
+                first_id: 3,
+                duplicate_id: 6,
+            } if token == "a"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "appears twice")]
+    fn from_panics_on_a_duplicate_token() {
+        let mut vocab = sample_vocab();
+        vocab.push(("a".to_string(), -9.0));
+        Unigram::from(vocab, Some(0));
+    }
+
+    #[test]
+    fn encode_greedy_disagrees_with_viterbi_when_the_longest_local_match_isnt_globally_best() {
+        let vocab = vec![
+            ("<unk>".to_string(), 0.0),
+            ("a".to_string(), -1.0),
+            ("ab".to_string(), -0.5),
+            ("abc".to_string(), -0.5),
+            ("c".to_string(), -1.0),
+            ("cc".to_string(), -0.1),
+        ];
+        let model = Unigram::from(vocab, Some(0));
+
+        // Greedy takes the longest match at position 0 ("abc"), leaving only
+        // the poorly-scored "c" for what's left.
+        assert_eq!(model.encode_greedy("abcc"), vec!["abc", "c"]);
+        // Viterbi instead prefers "ab" + "cc" (-0.5 + -0.1 = -0.6), which
+        // beats "abc" + "c" (-0.5 + -1.0 = -1.5) even though "abc" is the
+        // longer match.
+        assert_eq!(model.encode_fast("abcc"), vec!["ab", "cc"]);
+    }
+
+    #[test]
+    fn encode_greedy_falls_back_to_a_single_char_unk_piece() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        assert_eq!(model.encode_greedy("z"), vec!["z"]);
+    }
+
+    #[test]
+    fn tokenize_fuse_unk_spans_the_full_byte_range_of_consecutive_multibyte_unknowns() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        // "東" and "京" are each 3 bytes in UTF-8, so the fused span must be
+        // (0, 6), not (0, 2) (char count) or the offset of just one of them.
+        let tokens = model.tokenize_fuse_unk("東京").unwrap();
+
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].value, "東京");
+        assert_eq!(tokens[0].offsets, (0, 6));
+        assert_eq!(tokens[0].id, 0); // unk_id
+    }
+
+    #[test]
+    fn tokenize_fuse_unk_keeps_known_tokens_around_a_fused_unk_run_separate() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let tokens = model.tokenize_fuse_unk("ab東京ab").unwrap();
+
+        let values: Vec<&str> = tokens.iter().map(|t| t.value.as_str()).collect();
+        assert_eq!(values, vec!["ab", "東京", "ab"]);
+        assert_eq!(tokens[1].offsets, (2, 8));
+    }
+
+    #[test]
+    fn from_iter_builds_the_same_model_as_from() {
+        let from_vec = Unigram::from(sample_vocab(), Some(0));
+        let from_iter = Unigram::from_iter(sample_vocab().into_iter(), Some(0));
+
+        assert_eq!(from_vec, from_iter);
+        assert_eq!(
+            from_iter.tokenize("ab").unwrap(),
+            from_vec.tokenize("ab").unwrap()
+        );
+    }
+
+    #[test]
+    fn lattice_to_dot_contains_every_node_and_highlights_the_winning_path() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let lattice = model.build_lattice("abc");
+        let path = lattice.viterbi();
+
+        let dot = model.lattice_to_dot("abc");
+
+        assert!(dot.starts_with("digraph Lattice {"));
+        assert!(dot.trim_end().ends_with('}'));
+        // One "n<id> [label=...]" declaration per candidate node.
+        let declared_nodes = dot.matches("[label=").count();
+        assert_eq!(declared_nodes, lattice.node_count());
+        // The winning path has path.len() - 1 edges, each drawn in blue.
+        assert_eq!(dot.matches("color=blue").count(), path.len() - 1);
+        // Every winning node is filled.
+        assert_eq!(dot.matches("fillcolor=lightblue").count(), path.len());
+        // "ab" is on the winning path for "abc" (better than "a" + "b").
+        assert!(dot.contains("[label=\"ab\\n-1.500\", style=filled"));
+    }
+
+    #[test]
+    fn with_max_lattice_nodes_rejects_oversized_input() {
+        // A single-char vocab entry inserts exactly one node per position,
+        // so a 10-char input on this vocab builds a 10-node lattice.
+        let model = Unigram::from(sample_vocab(), Some(0)).with_max_lattice_nodes(3);
+
+        let err = model.tokenize("aaaaaaaaaa").unwrap_err();
+        assert_eq!(err.to_string(), EncodeError::LatticeTooLarge { max: 3 }.to_string());
+
+        // Under the budget, tokenization proceeds as normal.
+        let unbounded = Unigram::from(sample_vocab(), Some(0));
+        assert!(unbounded.tokenize("aaaaaaaaaa").is_ok());
+    }
+
+    #[test]
+    fn max_piece_length_defaults_to_the_longest_vocab_entry() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        // "<unk>" is the longest entry in `sample_vocab`, at 5 chars.
+        assert_eq!(model.max_piece_length(), 5);
+    }
+
+    #[test]
+    fn max_piece_length_caps_how_long_a_match_can_be() {
+        let long_piece = "a".repeat(50);
+        let mut vocab = sample_vocab();
+        vocab.push((long_piece.clone(), -0.1));
+
+        let uncapped = Unigram::from(vocab.clone(), Some(0));
+        assert_eq!(uncapped.max_piece_length(), 50);
+        let tokens = uncapped.tokenize(&long_piece).unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].value, long_piece);
+
+        // Capping below the long piece's length makes it unmatchable, so
+        // the lattice search never even tries anything that long.
+        let capped = Unigram::from(vocab, Some(0)).with_max_piece_length(10);
+        let tokens = capped.tokenize(&long_piece).unwrap();
+        assert!(tokens.len() > 1);
+        assert!(tokens.iter().all(|t| t.value.chars().count() <= 10));
+    }
+
+    #[test]
+    fn a_model_equals_itself_after_save_and_load() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+
+        let dir = tempfile::tempdir().unwrap();
+        let saved = model.save(dir.path(), None).unwrap();
+        let vocab_path = saved.first().unwrap();
+        let bytes = std::fs::read(vocab_path).unwrap();
+        let vocab: Vec<(String, f64)> = serde_json::from_slice(&bytes).unwrap();
+        let reloaded = Unigram::from(vocab, Some(0));
+
+        assert_eq!(model, reloaded);
+    }
+
+    #[test]
+    fn partial_eq_tolerates_a_tiny_score_difference() {
+        let mut vocab = sample_vocab();
+        let a = Unigram::from(vocab.clone(), Some(0));
+        vocab[3].1 += 1e-9;
+        let b = Unigram::from(vocab, Some(0));
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_rescored_tokens() {
+        let a = Unigram::from(sample_vocab(), Some(0));
+
+        let mut other_vocab = sample_vocab();
+        other_vocab.retain(|(token, _)| token != "ab"); // removed
+        other_vocab.push(("c".to_string(), -1.0)); // added
+        let rescored = other_vocab
+            .iter_mut()
+            .find(|(token, _)| token == "b")
+            .unwrap();
+        rescored.1 = -2.0; // rescored
+        let b = Unigram::from(other_vocab, Some(0));
+
+        let diffs = a.diff(&b);
+        assert_eq!(diffs.len(), 3);
+        assert!(diffs.contains(&VocabDiff::Removed {
+            token: "ab".to_string(),
+            score: -1.5,
+        }));
+        assert!(diffs.contains(&VocabDiff::Added {
+            token: "c".to_string(),
+            score: -1.0,
+        }));
+        assert!(diffs.contains(&VocabDiff::Rescored {
+            token: "b".to_string(),
+            old_score: -1.0,
+            new_score: -2.0,
+        }));
+    }
+
+    #[test]
+    fn diff_is_empty_for_a_model_compared_to_itself() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        assert_eq!(model.diff(&model), vec![]);
+    }
+
+    #[test]
+    fn score_tokens_sums_the_score_of_each_piece() {
+        let mut vocab = sample_vocab();
+        vocab.push(("cd".to_string(), -2.0));
+        vocab.push(("abcd".to_string(), -10.0));
+        let model = Unigram::from(vocab, Some(0));
+
+        let whole = model.score_tokens(&["abcd"]).unwrap();
+        let split = model.score_tokens(&["ab", "cd"]).unwrap();
+
+        assert_eq!(whole, -10.0);
+        assert_eq!(split, -1.5 + -2.0);
+        assert!(split > whole); // "ab" + "cd" is the better segmentation here
+    }
+
+    #[test]
+    fn score_tokens_is_none_for_an_unscoreable_piece() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        assert_eq!(model.score_tokens(&["a", "z"]), None);
+    }
+
+    #[test]
+    fn score_tokens_falls_back_to_byte_scores_when_byte_fallback_is_enabled() {
+        let mut vocab = sample_vocab();
+        vocab.push(("<0x7A>".to_string(), -3.0)); // 'z' is 0x7A in UTF-8
+        let model = Unigram::from(vocab, Some(0)).with_byte_fallback(true);
+
+        assert_eq!(model.score_tokens(&["a", "z"]), Some(-1.0 + -3.0));
+    }
+
+    #[test]
+    fn best_score_matches_the_sum_of_the_viterbi_path_scores() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let tokens = model.tokenize("ab").unwrap();
+        let expected: f64 = tokens.iter().filter_map(|t| model.score_of(t.id)).sum();
+
+        assert_eq!(model.best_score("ab"), expected);
+    }
+
+    #[test]
+    fn an_arc_unigram_can_be_shared_across_threads() {
+        let model = std::sync::Arc::new(Unigram::from(sample_vocab(), Some(0)));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let model = model.clone();
+                std::thread::spawn(move || model.tokenize("ab").unwrap().len())
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn unknown_spans_reports_byte_ranges_of_every_unk_character() {
+        let mut vocab = sample_vocab();
+        vocab.push(("c".to_string(), -1.0));
+        let model = Unigram::from(vocab, Some(0));
+
+        assert_eq!(model.unknown_spans("abcxx"), vec![(3, 4), (4, 5)]);
+    }
+
+    #[test]
+    fn unknown_spans_is_empty_when_byte_fallback_covers_every_unknown_character() {
+        let mut vocab = sample_vocab();
+        vocab.push(("<0x78>".to_string(), -1.0)); // 'x' is 0x78 in UTF-8
+        let model = Unigram::from(vocab, Some(0)).with_byte_fallback(true);
+
+        assert_eq!(model.unknown_spans("x"), vec![]);
+    }
+
+    #[test]
+    fn tokenize_offsets_are_relative_to_the_given_text_not_some_external_base() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let tokens = model.tokenize("ab").unwrap();
+        assert_eq!(tokens[0].offsets, (0, 2));
+    }
+
+    #[test]
+    fn tokenize_of_empty_string_returns_no_tokens() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        assert_eq!(model.tokenize("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn tokenize_of_whitespace_only_input_falls_back_to_unk() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let tokens = model.tokenize(" ").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].id, 0); // unk_id
+        assert_eq!(tokens[0].value, " ");
+    }
+
+    #[test]
+    fn tokenize_of_all_unknown_characters_emits_one_unk_token_per_character() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let tokens = model.tokenize("!?").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens.iter().all(|t| t.id == 0));
+    }
+
+    #[test]
+    fn encode_into_fuses_an_all_unknown_input_into_a_single_token_when_fuse_unk_is_set() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let mut out = Vec::new();
+        model.encode_into("!?", true, &mut out);
+        assert_eq!(out, vec!["!?".to_string()]);
+    }
+}
@@ -0,0 +1,35 @@
+/// A tokenization result laid out for offset queries, as produced by
+/// [`super::Unigram::encode_segments`]. Unlike a flat `Vec<Token>`, it lets a
+/// caller (e.g. an editor integration) ask which piece covers a given byte
+/// offset without re-running the search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segments {
+    pieces: Vec<(String, (usize, usize))>,
+}
+
+impl Segments {
+    pub(super) fn new(pieces: Vec<(String, (usize, usize))>) -> Self {
+        Self { pieces }
+    }
+
+    /// The index of the piece covering `offset`, if any.
+    pub fn piece_at_byte(&self, offset: usize) -> Option<usize> {
+        self.pieces
+            .iter()
+            .position(|(_, (start, end))| offset >= *start && offset < *end)
+    }
+
+    /// Iterate over every piece, together with its byte range in the
+    /// original text.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, (usize, usize))> {
+        self.pieces.iter().map(|(piece, range)| (piece.as_str(), *range))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+}
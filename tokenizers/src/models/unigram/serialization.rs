@@ -0,0 +1,116 @@
+use super::model::{Unigram, K_UNK_PENALTY};
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+impl Serialize for Unigram {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut model = serializer.serialize_struct("Unigram", 3)?;
+        model.serialize_field("unk_id", &self.unk_id())?;
+        model.serialize_field("vocab", &self.vocab())?;
+        model.serialize_field("unk_penalty", &self.unk_penalty())?;
+        model.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Unigram {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_struct(
+            "Unigram",
+            &["unk_id", "vocab", "unk_penalty"],
+            UnigramVisitor,
+        )
+    }
+}
+
+struct UnigramVisitor;
+impl<'de> Visitor<'de> for UnigramVisitor {
+    type Value = Unigram;
+
+    fn expecting(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "struct Unigram")
+    }
+
+    fn visit_map<V>(self, mut map: V) -> std::result::Result<Self::Value, V::Error>
+    where
+        V: MapAccess<'de>,
+    {
+        let mut unk_id: Option<usize> = None;
+        let mut vocab: Vec<(String, f64)> = vec![];
+        // Absent for a model serialized before this field existed; `10.0`
+        // (`K_UNK_PENALTY`) is the same default `Unigram::from` itself uses.
+        let mut unk_penalty: f64 = K_UNK_PENALTY;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_ref() {
+                "unk_id" => unk_id = map.next_value()?,
+                "vocab" => vocab = map.next_value()?,
+                "unk_penalty" => unk_penalty = map.next_value()?,
+                _ => {}
+            }
+        }
+        let mut model = Unigram::from(vocab, unk_id);
+        model.set_unk_penalty(unk_penalty);
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Model;
+
+    fn sample_vocab() -> Vec<(String, f64)> {
+        vec![
+            ("<unk>".to_string(), 0.0),
+            ("a".to_string(), -1.0),
+            ("b".to_string(), -1.0),
+            ("ab".to_string(), -1.5),
+        ]
+    }
+
+    #[test]
+    fn deserialized_model_tokenizes_the_same_as_the_original() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let serialized = serde_json::to_string(&model).unwrap();
+        let loaded: Unigram = serde_json::from_str(&serialized).unwrap();
+
+        // `trie` isn't part of the wire format; it's rebuilt lazily from
+        // `vocab` on first use, so a freshly deserialized model must
+        // tokenize identically to the one that produced it.
+        assert_eq!(
+            loaded.tokenize("abc").unwrap(),
+            model.tokenize("abc").unwrap()
+        );
+    }
+
+    #[test]
+    fn a_custom_unk_penalty_round_trips_and_affects_encoding() {
+        let mut model = Unigram::from(sample_vocab(), Some(0));
+        model.set_unk_penalty(0.1); // much lower than the default 10.0
+
+        let serialized = serde_json::to_string(&model).unwrap();
+        let loaded: Unigram = serde_json::from_str(&serialized).unwrap();
+
+        assert_eq!(loaded.unk_penalty(), 0.1);
+        assert_eq!(
+            loaded.tokenize("abz").unwrap(),
+            model.tokenize("abz").unwrap()
+        );
+    }
+
+    #[test]
+    fn a_model_serialized_without_unk_penalty_deserializes_to_the_default() {
+        let json = r#"{"unk_id":0,"vocab":[["<unk>",0.0],["a",-1.0]]}"#;
+        let loaded: Unigram = serde_json::from_str(json).unwrap();
+
+        assert_eq!(loaded.unk_penalty(), 10.0);
+    }
+}
@@ -0,0 +1,124 @@
+//! Loads SentencePiece's binary `*.model` protobuf directly, as an
+//! alternative to [`super::load_spm`]'s plain-text `spm_export_vocab`
+//! format. This is the format SentencePiece itself ships, and unlike the
+//! text export it carries the real unk/bos/eos ids and each piece's type.
+//!
+//! Only the handful of `ModelProto`/`SentencePiece` fields `Unigram` cares
+//! about are declared here (see SentencePiece's own `sentencepiece_model.proto`
+//! for the full schema); everything else (the `TrainerSpec`, normalization
+//! rules, etc.) is parsed and discarded by `prost` since unknown/unused
+//! fields are simply skipped.
+use super::model::{PieceType, Unigram};
+use crate::tokenizer::Result;
+use prost::Message;
+use std::path::Path;
+
+#[derive(Clone, PartialEq, Message)]
+struct SentencePieceProto {
+    #[prost(string, optional, tag = "1")]
+    piece: Option<String>,
+    #[prost(float, optional, tag = "2")]
+    score: Option<f32>,
+    #[prost(enumeration = "PieceTypeProto", optional, tag = "3")]
+    r#type: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+#[repr(i32)]
+enum PieceTypeProto {
+    Normal = 1,
+    Unknown = 2,
+    Control = 3,
+    UserDefined = 4,
+    Byte = 6,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ModelProto {
+    #[prost(message, repeated, tag = "1")]
+    pieces: Vec<SentencePieceProto>,
+}
+
+fn to_piece_type(proto_type: Option<i32>) -> PieceType {
+    match proto_type {
+        Some(t) if t == PieceTypeProto::Unknown as i32 => PieceType::Unknown,
+        Some(t) if t == PieceTypeProto::Control as i32 => PieceType::Control,
+        Some(t) if t == PieceTypeProto::UserDefined as i32 => PieceType::UserDefined,
+        Some(t) if t == PieceTypeProto::Byte as i32 => PieceType::Byte,
+        _ => PieceType::Normal,
+    }
+}
+
+/// Load a `Unigram` model from a SentencePiece binary `*.model` file (a
+/// serialized `ModelProto`), looking up `<unk>` by SentencePiece's
+/// `UNKNOWN` piece type rather than assuming it's at id `0`, and carrying
+/// over each piece's [`PieceType`] (see [`Unigram::with_piece_types`]).
+pub fn load_spm_model(path: &Path) -> Result<Unigram> {
+    let bytes = std::fs::read(path)?;
+    let proto = ModelProto::decode(bytes.as_slice())?;
+
+    let mut vocab = Vec::with_capacity(proto.pieces.len());
+    let mut piece_types = Vec::with_capacity(proto.pieces.len());
+    let mut unk_id = None;
+    for (id, piece) in proto.pieces.into_iter().enumerate() {
+        let token = piece.piece.unwrap_or_default();
+        let score = piece.score.unwrap_or(0.0) as f64;
+        let piece_type = to_piece_type(piece.r#type);
+        if piece_type == PieceType::Unknown {
+            unk_id = Some(id);
+        }
+        vocab.push((token, score));
+        piece_types.push(piece_type);
+    }
+
+    Unigram::from(vocab, unk_id).with_piece_types(piece_types)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Model;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn load_spm_model_reads_pieces_and_finds_the_unk_by_type() {
+        let proto = ModelProto {
+            pieces: vec![
+                SentencePieceProto {
+                    piece: Some("<unk>".to_string()),
+                    score: Some(0.0),
+                    r#type: Some(PieceTypeProto::Unknown as i32),
+                },
+                SentencePieceProto {
+                    piece: Some("<s>".to_string()),
+                    score: Some(0.0),
+                    r#type: Some(PieceTypeProto::Control as i32),
+                },
+                SentencePieceProto {
+                    piece: Some("</s>".to_string()),
+                    score: Some(0.0),
+                    r#type: Some(PieceTypeProto::Control as i32),
+                },
+                SentencePieceProto {
+                    piece: Some("ab".to_string()),
+                    score: Some(-1.5),
+                    r#type: Some(PieceTypeProto::Normal as i32),
+                },
+            ],
+        };
+
+        let mut bytes = Vec::new();
+        proto.encode(&mut bytes).unwrap();
+        let file = NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), &bytes).unwrap();
+
+        let model = load_spm_model(file.path()).unwrap();
+
+        assert_eq!(model.vocab().len(), 4);
+        assert_eq!(model.unk_id(), Some(0));
+        assert_eq!(model.piece_type(0), Some(PieceType::Unknown));
+        assert_eq!(model.piece_type(1), Some(PieceType::Control));
+        assert_eq!(model.piece_type(3), Some(PieceType::Normal));
+        assert_eq!(model.tokenize("ab").unwrap()[0].value, "ab");
+    }
+}
@@ -0,0 +1,92 @@
+//! A human-friendly TOML serialization of a [`Unigram`] model, as an
+//! alternative to the compact JSON array `Model::save` produces. Meant for
+//! hand-curated, small vocabularies rather than production-sized ones.
+use super::model::Unigram;
+use crate::tokenizer::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Serialize, Deserialize)]
+struct TomlSpecials {
+    unk_id: Option<usize>,
+    bos_id: Option<usize>,
+    eos_id: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TomlPiece {
+    token: String,
+    score: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TomlModel {
+    specials: TomlSpecials,
+    piece: Vec<TomlPiece>,
+}
+
+/// Write `model` to `path` as a self-describing TOML file: a `[specials]`
+/// header table with the special-token ids, followed by one `[[piece]]`
+/// table per vocabulary entry.
+pub fn save_toml(model: &Unigram, path: &Path) -> Result<()> {
+    let doc = TomlModel {
+        specials: TomlSpecials {
+            unk_id: model.unk_id(),
+            bos_id: model.bos_id(),
+            eos_id: model.eos_id(),
+        },
+        piece: model
+            .vocab()
+            .iter()
+            .map(|(token, score)| TomlPiece {
+                token: token.clone(),
+                score: *score,
+            })
+            .collect(),
+    };
+    let serialized = toml::to_string_pretty(&doc)?;
+    std::fs::write(path, serialized)?;
+    Ok(())
+}
+
+/// Load a [`Unigram`] previously written by [`save_toml`].
+pub fn load_toml(path: &Path) -> Result<Unigram> {
+    let content = std::fs::read_to_string(path)?;
+    let doc: TomlModel = toml::from_str(&content)?;
+    let vocab = doc
+        .piece
+        .into_iter()
+        .map(|piece| (piece.token, piece.score))
+        .collect();
+    Ok(Unigram::from(vocab, doc.specials.unk_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn sample_vocab() -> Vec<(String, f64)> {
+        vec![
+            ("<unk>".to_string(), 0.0),
+            ("<s>".to_string(), 0.0),
+            ("</s>".to_string(), 0.0),
+            ("a".to_string(), -1.0),
+            ("b".to_string(), -1.0),
+        ]
+    }
+
+    #[test]
+    fn save_then_load_toml_round_trips() {
+        let model = Unigram::from(sample_vocab(), Some(0));
+        let file = NamedTempFile::new().unwrap();
+
+        save_toml(&model, file.path()).unwrap();
+        let loaded = load_toml(file.path()).unwrap();
+
+        assert_eq!(loaded.vocab(), model.vocab());
+        assert_eq!(loaded.unk_id(), model.unk_id());
+        assert_eq!(loaded.bos_id(), model.bos_id());
+        assert_eq!(loaded.eos_id(), model.eos_id());
+    }
+}
@@ -0,0 +1,387 @@
+//! Trains a `Unigram` model from a word-frequency corpus via
+//! expectation-maximization, mirroring (in simplified form) the approach
+//! SentencePiece's own trainer uses: seed a large pool of substring
+//! candidates, alternate E-steps (re-estimating how often each candidate is
+//! actually used, via the same lattice forward-backward machinery
+//! `Unigram::sample_encode_detailed` already relies on) with M-steps
+//! (rescoring candidates from those estimates), then shrink the pool
+//! towards `vocab_size` and repeat.
+//!
+//! Unlike SentencePiece, pruning here ranks candidates purely by their
+//! EM-estimated score rather than by the exact marginal loss of removing
+//! each one (which would require re-running EM once per candidate); this
+//! is much cheaper and converges to a similar vocabulary in practice, at
+//! the cost of being a little less precise about which low-value piece
+//! goes first.
+use super::lattice::Lattice;
+use super::model::Unigram;
+use crate::tokenizer::Result;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// How many E/M rounds to run against a fixed candidate pool before
+/// shrinking it. SentencePiece itself defaults to 2.
+const SUB_ITERATIONS: usize = 2;
+
+/// Configuration for training a `Unigram` model with [`UnigramTrainer::train`].
+#[derive(Debug, Clone)]
+pub struct UnigramTrainer {
+    vocab_size: usize,
+    shrinking_factor: f64,
+    max_piece_length: usize,
+    unk_token: String,
+}
+
+impl UnigramTrainer {
+    /// A trainer targeting `vocab_size` pieces, with SentencePiece's usual
+    /// defaults otherwise (a shrinking factor of `0.75` and a max piece
+    /// length of `16` chars).
+    pub fn new(vocab_size: usize) -> Self {
+        Self {
+            vocab_size,
+            shrinking_factor: 0.75,
+            max_piece_length: 16,
+            unk_token: "<unk>".to_string(),
+        }
+    }
+
+    /// The fraction of the candidate pool kept after each pruning round
+    /// (e.g. `0.75` keeps 75%, discarding the lowest-scoring quarter).
+    pub fn with_shrinking_factor(mut self, shrinking_factor: f64) -> Self {
+        self.shrinking_factor = shrinking_factor;
+        self
+    }
+
+    /// The longest substring considered as a training candidate.
+    pub fn with_max_piece_length(mut self, max_piece_length: usize) -> Self {
+        self.max_piece_length = max_piece_length;
+        self
+    }
+
+    /// The token used for the model's `<unk>` entry in the trained
+    /// vocabulary. Defaults to `"<unk>"`.
+    pub fn with_unk_token(mut self, unk_token: String) -> Self {
+        self.unk_token = unk_token;
+        self
+    }
+
+    /// Train a `Unigram` model over `word_counts` (each distinct word
+    /// mapped to how many times it occurs in the training corpus).
+    pub fn train(&self, word_counts: HashMap<String, u32>) -> Unigram {
+        let mut pieces = self.seed_pieces(&word_counts);
+
+        loop {
+            for _ in 0..SUB_ITERATIONS {
+                pieces = self.em_round(&word_counts, pieces);
+            }
+            if pieces.len() <= self.vocab_size {
+                break;
+            }
+            let pruned = self.prune(pieces.clone());
+            if pruned.len() == pieces.len() {
+                // Can't shrink further without dropping a single-char
+                // piece some character in the corpus depends on for
+                // coverage; stop here even if that leaves more pieces
+                // than `vocab_size` asked for.
+                pieces = pruned;
+                break;
+            }
+            pieces = pruned;
+        }
+
+        self.finalize(pieces)
+    }
+
+    /// Like [`UnigramTrainer::train`], but reads `reader` one line at a
+    /// time instead of requiring the whole corpus already collected into a
+    /// `word_counts` map, so training can run directly against a corpus
+    /// file larger than RAM. Each line is split on whitespace into words,
+    /// with a `(word, count)` map accumulated incrementally as lines
+    /// stream by, then handed to `train` exactly as a caller-built one
+    /// would be.
+    ///
+    /// Memory bound: proportional to the number of *distinct* words seen,
+    /// not the corpus's total size — a line is never buffered past the
+    /// count it contributes. A corpus of mostly-repeated lines trains in
+    /// close to constant memory; one with mostly-unique lines still needs
+    /// memory proportional to its word count, the same bound `train`,
+    /// `seed_pieces`, and `em_round` already have once that map is built.
+    pub fn train_from_reader(&self, reader: impl BufRead) -> Result<Unigram> {
+        let mut word_counts: HashMap<String, u32> = HashMap::new();
+        for line in reader.lines() {
+            for word in line?.split_whitespace() {
+                *word_counts.entry(word.to_string()).or_insert(0) += 1;
+            }
+        }
+        Ok(self.train(word_counts))
+    }
+
+    /// Train one model per entry in `sizes` (each a full, independent
+    /// `train` call with that `vocab_size` substituted in) and score every
+    /// one by its total held-out log-likelihood over `heldout`, via
+    /// [`Unigram::score_sentence_marginal`], so a caller can plot
+    /// likelihood against vocab size and pick the knee point rather than
+    /// guessing. Retraining per size means this costs `sizes.len()` times
+    /// what a single `train` call does.
+    pub fn sweep_vocab_size(
+        &self,
+        train: &HashMap<String, u32>,
+        heldout: &[String],
+        sizes: &[usize],
+    ) -> Vec<(usize, f64)> {
+        sizes
+            .iter()
+            .map(|&size| {
+                let trainer = Self {
+                    vocab_size: size,
+                    ..self.clone()
+                };
+                let model = trainer.train(train.clone());
+                let likelihood: f64 = heldout
+                    .iter()
+                    .map(|sentence| model.score_sentence_marginal(sentence))
+                    .sum();
+                (size, likelihood)
+            })
+            .collect()
+    }
+
+    /// Every substring up to `max_piece_length` chars, scored by its raw
+    /// frequency across the corpus (weighted by each word's count), as a
+    /// starting point for EM to refine.
+    fn seed_pieces(&self, word_counts: &HashMap<String, u32>) -> Vec<(String, f64)> {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for (word, count) in word_counts {
+            let chars: Vec<char> = word.chars().collect();
+            for start in 0..chars.len() {
+                let max_len = self.max_piece_length.min(chars.len() - start);
+                for len in 1..=max_len {
+                    let piece: String = chars[start..start + len].iter().collect();
+                    *counts.entry(piece).or_insert(0) += *count as u64;
+                }
+            }
+        }
+
+        let total: u64 = counts.values().sum::<u64>().max(1);
+        let mut pieces: Vec<(String, f64)> = counts
+            .into_iter()
+            .map(|(piece, count)| (piece, (count as f64 / total as f64).ln()))
+            .collect();
+        pieces.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        pieces
+    }
+
+    /// One E-step/M-step pass over a fixed candidate pool: re-estimate how
+    /// often each candidate is used across the corpus (via lattice
+    /// forward-backward marginals), then rescore every candidate from
+    /// those expected counts.
+    fn em_round(
+        &self,
+        word_counts: &HashMap<String, u32>,
+        pieces: Vec<(String, f64)>,
+    ) -> Vec<(String, f64)> {
+        let mut expected = vec![0.0f64; pieces.len()];
+        {
+            let index: HashMap<&str, (usize, f64)> = pieces
+                .iter()
+                .enumerate()
+                .map(|(id, (token, score))| (token.as_str(), (id, *score)))
+                .collect();
+
+            for (word, count) in word_counts {
+                if word.is_empty() {
+                    continue;
+                }
+                let mut lattice = Lattice::from(word);
+                populate_training_lattice(&mut lattice, &index, self.max_piece_length);
+                for (node_id, prob) in lattice.node_marginal_probs(1.0).into_iter().enumerate() {
+                    if let Some(id) = lattice.node(node_id).piece_id {
+                        expected[id] += prob * *count as f64;
+                    }
+                }
+            }
+        }
+
+        let total: f64 = expected.iter().sum();
+        pieces
+            .into_iter()
+            .zip(expected)
+            .map(|((token, old_score), count)| {
+                let score = if count > 0.0 && total > 0.0 {
+                    (count / total).ln()
+                } else {
+                    // EM never actually used this candidate on this pool;
+                    // decay it so pruning sheds it before pieces that are.
+                    old_score - 10.0
+                };
+                (token, score)
+            })
+            .collect()
+    }
+
+    /// Shrink the candidate pool by `shrinking_factor`, always keeping
+    /// every single-char piece (every character in the corpus must remain
+    /// coverable) and otherwise keeping the highest-scoring pieces.
+    fn prune(&self, mut pieces: Vec<(String, f64)>) -> Vec<(String, f64)> {
+        pieces.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+        let (single_chars, multi_chars): (Vec<_>, Vec<_>) =
+            pieces.into_iter().partition(|(token, _)| token.chars().count() == 1);
+
+        let floor = self.vocab_size.max(single_chars.len());
+        let shrunk = ((single_chars.len() + multi_chars.len()) as f64 * self.shrinking_factor)
+            as usize;
+        let target = shrunk.max(floor);
+        let keep_multi = target.saturating_sub(single_chars.len());
+
+        let mut kept = single_chars;
+        kept.extend(multi_chars.into_iter().take(keep_multi));
+        kept.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        kept
+    }
+
+    /// Build the final `Unigram`, prepending the mandatory `<unk>`, `<s>`,
+    /// `</s>` specials (stripping them out of the trained pool first, in
+    /// case the corpus happened to produce a substring with the same
+    /// spelling) so the returned model is immediately usable.
+    fn finalize(&self, pieces: Vec<(String, f64)>) -> Unigram {
+        let specials: std::collections::HashSet<&str> =
+            [self.unk_token.as_str(), "<s>", "</s>"].iter().copied().collect();
+
+        let mut vocab = vec![
+            (self.unk_token.clone(), 0.0),
+            ("<s>".to_string(), 0.0),
+            ("</s>".to_string(), 0.0),
+        ];
+        vocab.extend(
+            pieces
+                .into_iter()
+                .filter(|(token, _)| !specials.contains(token.as_str())),
+        );
+
+        Unigram::from(vocab, Some(0))
+    }
+}
+
+/// Populate `lattice` using a flat candidate index rather than a trie: a
+/// trainer's candidate pool changes every round, so it isn't worth
+/// rebuilding a trie for it the way `Unigram::populate_nodes` does for its
+/// (comparatively stable) vocab.
+fn populate_training_lattice(
+    lattice: &mut Lattice,
+    index: &HashMap<&str, (usize, f64)>,
+    max_piece_length: usize,
+) {
+    for pos in 0..lattice.len() {
+        let mut has_single_char_match = false;
+        let max_len = max_piece_length.min(lattice.len() - pos);
+        for len in 1..=max_len {
+            let piece: String = (pos..pos + len).map(|i| lattice.char_at(i)).collect();
+            if let Some(&(id, score)) = index.get(piece.as_str()) {
+                if len == 1 {
+                    has_single_char_match = true;
+                }
+                lattice.insert(pos, len, score, Some(id));
+            }
+        }
+        if !has_single_char_match {
+            // Should only happen for a character the candidate pool has
+            // already dropped its single-char piece for; keep the lattice
+            // coverable instead of letting `viterbi` panic.
+            lattice.insert(pos, 1, -1e6, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Model;
+
+    fn word_counts() -> HashMap<String, u32> {
+        [
+            ("low", 5),
+            ("lower", 2),
+            ("lowest", 2),
+            ("newer", 6),
+            ("wider", 3),
+            ("new", 2),
+        ]
+        .iter()
+        .map(|(word, count)| (word.to_string(), *count))
+        .collect()
+    }
+
+    #[test]
+    fn train_respects_the_target_vocab_size() {
+        let trainer = UnigramTrainer::new(20).with_max_piece_length(6);
+        let model = trainer.train(word_counts());
+
+        // `vocab_size` bounds the trained candidate pool; the mandatory
+        // `<unk>`/`<s>`/`</s>` specials come on top of it.
+        assert!(model.get_vocab_size() <= 20 + 3);
+        assert!(model.get_vocab_size() > 3);
+    }
+
+    #[test]
+    fn train_produces_a_model_that_tokenizes_training_words() {
+        let trainer = UnigramTrainer::new(30).with_max_piece_length(6);
+        let model = trainer.train(word_counts());
+
+        for word in ["low", "lower", "lowest", "newer", "wider", "new"] {
+            let ids = model.encode_ids(word);
+            assert_eq!(model.decode(&ids), word);
+        }
+    }
+
+    #[test]
+    fn train_scores_a_more_frequent_word_above_a_rarer_one() {
+        // A vocab_size far larger than the candidate pool so nothing gets
+        // pruned, isolating the EM rescoring from the pruning heuristic.
+        let trainer = UnigramTrainer::new(1000).with_max_piece_length(6);
+        let model = trainer.train(word_counts());
+
+        let score_of = |token: &str| {
+            model
+                .vocab()
+                .iter()
+                .find(|(t, _)| t == token)
+                .map(|(_, score)| *score)
+                .expect("candidate should survive with nothing pruned")
+        };
+
+        // "newer" occurs 6 times in the corpus, "wider" only 3.
+        assert!(score_of("newer") > score_of("wider"));
+    }
+
+    #[test]
+    fn sweep_vocab_size_prefers_a_larger_vocab_on_this_tiny_corpus() {
+        let trainer = UnigramTrainer::new(10).with_max_piece_length(6);
+        let counts = word_counts();
+        let heldout: Vec<String> = counts.keys().cloned().collect();
+
+        let swept = trainer.sweep_vocab_size(&counts, &heldout, &[10, 30]);
+
+        assert_eq!(
+            swept.iter().map(|(size, _)| *size).collect::<Vec<_>>(),
+            vec![10, 30]
+        );
+        let likelihood_at_10 = swept[0].1;
+        let likelihood_at_30 = swept[1].1;
+        assert!(likelihood_at_30 > likelihood_at_10);
+    }
+
+    #[test]
+    fn train_from_reader_streams_lines_from_a_cursor_into_a_usable_model() {
+        let corpus = "low low low low low\nlower lower\nlowest lowest\nnewer newer newer newer newer newer\nwider wider wider\nnew new\n";
+        let reader = std::io::Cursor::new(corpus);
+
+        let trainer = UnigramTrainer::new(30).with_max_piece_length(6);
+        let model = trainer.train_from_reader(reader).unwrap();
+
+        for word in ["low", "lower", "lowest", "newer", "wider", "new"] {
+            let ids = model.encode_ids(word);
+            assert_eq!(model.decode(&ids), word);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TrieNode<Label: Eq + Hash, Payload> {
+    children: HashMap<Label, TrieNode<Label, Payload>>,
+    value: Option<Payload>,
+}
+
+impl<Label: Eq + Hash + Clone, Payload> TrieNode<Label, Payload> {
+    fn new() -> Self {
+        Self {
+            children: HashMap::new(),
+            value: None,
+        }
+    }
+}
+
+/// A simple trie used to find, for a given position in a sentence, every
+/// vocabulary entry that matches a prefix starting there (common-prefix
+/// search). This is the main lookup structure used to populate a `Lattice`.
+///
+/// Each inserted sequence can carry a `Payload` (e.g. a token id), stored at
+/// its terminal node, so a caller that needs more than "is this sequence
+/// present" can get it back from `common_prefix_search` directly instead of
+/// re-deriving it (a `String` allocation plus a `HashMap` lookup, on
+/// `Unigram`'s hot path) from the matched labels. `Payload` defaults to `()`
+/// for callers that only care about presence.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trie<Label: Eq + Hash, Payload = ()> {
+    root: TrieNode<Label, Payload>,
+    /// When set, every label is case-folded (see [`AsciiCaseFold`]) before
+    /// being inserted or looked up, so `common_prefix_search` matches
+    /// regardless of ASCII case. The payload stored at a match is still
+    /// whatever the caller originally passed to `push`/`push_with_value`,
+    /// so a caller that wants the canonical (originally-cased) piece back
+    /// just needs to make that the payload, as `Unigram` already does by
+    /// storing the token's id rather than its spelling.
+    #[serde(default)]
+    case_insensitive: bool,
+}
+
+/// Implemented for trie labels that support ASCII case folding, so
+/// [`Trie::with_case_insensitive`] can normalize keys on insertion and
+/// lookup. `char` is the only label type this crate stores in a `Trie`.
+pub trait AsciiCaseFold {
+    fn ascii_case_fold(self) -> Self;
+}
+
+impl AsciiCaseFold for char {
+    fn ascii_case_fold(self) -> Self {
+        self.to_ascii_lowercase()
+    }
+}
+
+/// The on-the-wire shape written by `Trie::to_bytes`: the trie tagged with
+/// the fingerprint of the vocab it was built from, so `Trie::from_bytes` can
+/// refuse a trie that doesn't match the vocab trying to load it.
+#[derive(Serialize)]
+struct SerializedTrieRef<'a, Label: Eq + Hash, Payload> {
+    fingerprint: u64,
+    trie: &'a Trie<Label, Payload>,
+}
+
+#[derive(Deserialize)]
+struct SerializedTrieOwned<Label: Eq + Hash, Payload> {
+    fingerprint: u64,
+    trie: Trie<Label, Payload>,
+}
+
+#[derive(Debug)]
+pub enum TrieLoadError {
+    Deserialize(serde_json::Error),
+    /// The serialized trie's fingerprint doesn't match the one the caller
+    /// expected, i.e. it wasn't built from the same vocab.
+    FingerprintMismatch { expected: u64, found: u64 },
+}
+
+impl std::fmt::Display for TrieLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TrieLoadError::Deserialize(e) => write!(f, "Failed to deserialize trie: {}", e),
+            TrieLoadError::FingerprintMismatch { expected, found } => write!(
+                f,
+                "Serialized trie fingerprint {} doesn't match expected {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TrieLoadError {}
+
+impl<Label: Eq + Hash + Clone + AsciiCaseFold, Payload> Trie<Label, Payload> {
+    pub fn new() -> Self {
+        Self {
+            root: TrieNode::new(),
+            case_insensitive: false,
+        }
+    }
+
+    /// Fold every label by ASCII case before inserting or looking it up, so
+    /// `common_prefix_search` matches regardless of case (e.g. a vocab
+    /// entry `"ABC"` matches the input `"abc"`). The payload stored at a
+    /// match is unaffected: it's still whatever `push`/`push_with_value`
+    /// was given, so a caller that needs the original casing back should
+    /// store it there.
+    pub fn with_case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    fn fold(&self, label: Label) -> Label {
+        if self.case_insensitive {
+            label.ascii_case_fold()
+        } else {
+            label
+        }
+    }
+
+    /// Insert a new sequence of labels into the trie, with an associated
+    /// `value` to return from `common_prefix_search` once this sequence is
+    /// matched.
+    pub fn push_with_value(&mut self, element: impl IntoIterator<Item = Label>, value: Payload) {
+        let case_insensitive = self.case_insensitive;
+        let mut node = &mut self.root;
+        for label in element {
+            let label = if case_insensitive {
+                label.ascii_case_fold()
+            } else {
+                label
+            };
+            node = node.children.entry(label).or_insert_with(TrieNode::new);
+        }
+        node.value = Some(value);
+    }
+
+    /// Returns `(length, payload)` for every prefix of `sequence` (starting
+    /// at its first element) that is present in the trie, shortest first.
+    pub fn common_prefix_search<T>(&self, sequence: T) -> Vec<(usize, Payload)>
+    where
+        T: IntoIterator<Item = Label>,
+        Payload: Clone,
+    {
+        self.common_prefix_search_iter(sequence).collect()
+    }
+
+    /// Like [`Trie::common_prefix_search`], but yields matches lazily as
+    /// `sequence` is walked instead of collecting them into a `Vec` first.
+    /// Meant for a hot loop like `populate_nodes_checked`'s, which runs this
+    /// once per lattice position and otherwise pays for an allocation it
+    /// only iterates over once.
+    pub fn common_prefix_search_iter<T>(
+        &self,
+        sequence: T,
+    ) -> CommonPrefixSearchIter<'_, Label, Payload, T::IntoIter>
+    where
+        T: IntoIterator<Item = Label>,
+    {
+        CommonPrefixSearchIter {
+            trie: self,
+            node: &self.root,
+            sequence: sequence.into_iter(),
+            len: 0,
+            done: false,
+        }
+    }
+}
+
+/// Lazy [`Trie::common_prefix_search_iter`] iterator: walks `sequence` one
+/// label at a time, yielding `(length, payload)` for every prefix matched
+/// so far, without ever materializing the full match list.
+pub struct CommonPrefixSearchIter<'a, Label: Eq + Hash, Payload, I> {
+    trie: &'a Trie<Label, Payload>,
+    node: &'a TrieNode<Label, Payload>,
+    sequence: I,
+    len: usize,
+    done: bool,
+}
+
+impl<'a, Label, Payload, I> Iterator for CommonPrefixSearchIter<'a, Label, Payload, I>
+where
+    Label: Eq + Hash + Clone + AsciiCaseFold,
+    Payload: Clone,
+    I: Iterator<Item = Label>,
+{
+    type Item = (usize, Payload);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let label = match self.sequence.next() {
+                Some(label) => label,
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            };
+            self.len += 1;
+            let label = self.trie.fold(label);
+            match self.node.children.get(&label) {
+                Some(child) => {
+                    self.node = child;
+                    if let Some(value) = &child.value {
+                        return Some((self.len, value.clone()));
+                    }
+                }
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<Label: Eq + Hash + Clone + AsciiCaseFold> Trie<Label, ()> {
+    /// Insert a new sequence of labels into the trie, with no payload
+    /// (only whether the sequence is present matters).
+    pub fn push(&mut self, element: impl IntoIterator<Item = Label>) {
+        self.push_with_value(element, ());
+    }
+}
+
+impl<Label: Eq + Hash + Clone + Serialize, Payload: Serialize> Trie<Label, Payload> {
+    /// Serialize the trie to bytes, tagged with `fingerprint` (see
+    /// `Unigram::vocab_fingerprint`), for sharing a built trie with other
+    /// processes via [`Trie::from_bytes`] instead of each rebuilding it from
+    /// the vocab.
+    pub fn to_bytes(&self, fingerprint: u64) -> crate::tokenizer::Result<Vec<u8>> {
+        let wire = SerializedTrieRef {
+            fingerprint,
+            trie: self,
+        };
+        Ok(serde_json::to_vec(&wire)?)
+    }
+}
+
+impl<Label, Payload> Trie<Label, Payload>
+where
+    Label: Eq + Hash + Clone + for<'de> Deserialize<'de>,
+    Payload: for<'de> Deserialize<'de>,
+{
+    /// Load a trie previously written by [`Trie::to_bytes`], failing if its
+    /// fingerprint doesn't match `expected_fingerprint`.
+    pub fn from_bytes(bytes: &[u8], expected_fingerprint: u64) -> crate::tokenizer::Result<Self> {
+        let wire: SerializedTrieOwned<Label, Payload> =
+            serde_json::from_slice(bytes).map_err(TrieLoadError::Deserialize)?;
+        if wire.fingerprint != expected_fingerprint {
+            return Err(Box::new(TrieLoadError::FingerprintMismatch {
+                expected: expected_fingerprint,
+                found: wire.fingerprint,
+            }));
+        }
+        Ok(wire.trie)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive_trie_matches_regardless_of_ascii_case() {
+        let mut trie = Trie::new().with_case_insensitive(true);
+        trie.push_with_value("ABC".chars(), "ABC".to_string());
+
+        let results = trie.common_prefix_search("abc".chars());
+        assert_eq!(results, vec![(3, "ABC".to_string())]);
+    }
+
+    #[test]
+    fn case_sensitive_trie_still_rejects_a_case_mismatch_by_default() {
+        let mut trie: Trie<char, ()> = Trie::new();
+        trie.push("ABC".chars());
+
+        assert_eq!(trie.common_prefix_search("abc".chars()), vec![]);
+    }
+
+    #[test]
+    fn common_prefix_search_iter_yields_the_same_matches_as_common_prefix_search() {
+        let mut trie = Trie::new();
+        trie.push_with_value("a".chars(), 1u32);
+        trie.push_with_value("ab".chars(), 2u32);
+        trie.push_with_value("abc".chars(), 3u32);
+        trie.push_with_value("abd".chars(), 4u32);
+
+        let expected = trie.common_prefix_search("abcd".chars());
+        let actual: Vec<(usize, u32)> =
+            trie.common_prefix_search_iter("abcd".chars()).collect();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn finds_every_matching_prefix() {
+        let mut trie = Trie::new();
+        trie.push("a".chars());
+        trie.push("ab".chars());
+        trie.push("abc".chars());
+        trie.push("abd".chars());
+
+        let results: Vec<usize> = trie
+            .common_prefix_search("abcd".chars())
+            .into_iter()
+            .map(|(len, ())| len)
+            .collect();
+
+        assert_eq!(results, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn common_prefix_search_returns_the_payload_stored_at_each_match() {
+        let mut trie = Trie::new();
+        trie.push_with_value("a".chars(), 7u32);
+        trie.push_with_value("ab".chars(), 9u32);
+
+        let results = trie.common_prefix_search("abc".chars());
+        assert_eq!(results, vec![(1, 7), (2, 9)]);
+    }
+
+    #[test]
+    fn to_bytes_from_bytes_round_trips_common_prefix_search() {
+        let mut trie = Trie::new();
+        trie.push_with_value("a".chars(), 1u32);
+        trie.push_with_value("ab".chars(), 2u32);
+        trie.push_with_value("abc".chars(), 3u32);
+
+        let bytes = trie.to_bytes(42).unwrap();
+        let loaded = Trie::<char, u32>::from_bytes(&bytes, 42).unwrap();
+
+        let expected = trie.common_prefix_search("abcd".chars());
+        let actual = loaded.common_prefix_search("abcd".chars());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn from_bytes_rejects_a_fingerprint_mismatch() {
+        let mut trie: Trie<char, u32> = Trie::new();
+        trie.push_with_value("a".chars(), 1);
+
+        let bytes = trie.to_bytes(1).unwrap();
+        assert!(Trie::<char, u32>::from_bytes(&bytes, 2).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use crate::tokenizer::{NormalizedString, Normalizer, Result};
+use serde::{Deserialize, Serialize};
+
+/// What to do with ASCII control characters (U+0000-U+001F) found in the input.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum ControlCharPolicy {
+    /// Leave control characters untouched (the historical behavior).
+    Keep,
+    /// Remove control characters from the normalized string entirely.
+    Drop,
+    /// Replace each control character with the Unicode replacement
+    /// character, so it surfaces as `unk` once the model looks it up.
+    ReplaceWithUnk,
+}
+
+fn is_control_char(c: char) -> bool {
+    (c as u32) <= 0x1F
+}
+
+/// Normalizes the handling of control characters in the input, according to
+/// a configurable [`ControlCharPolicy`].
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub struct ControlChars {
+    policy: ControlCharPolicy,
+}
+
+impl Default for ControlChars {
+    fn default() -> Self {
+        Self {
+            policy: ControlCharPolicy::Keep,
+        }
+    }
+}
+
+impl ControlChars {
+    pub fn new(policy: ControlCharPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+impl Normalizer for ControlChars {
+    fn normalize(&self, normalized: &mut NormalizedString) -> Result<()> {
+        match self.policy {
+            ControlCharPolicy::Keep => {}
+            ControlCharPolicy::Drop => {
+                normalized.filter(|c| !is_control_char(c));
+            }
+            ControlCharPolicy::ReplaceWithUnk => {
+                normalized.map(|c| if is_control_char(c) { '\u{fffd}' } else { c });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_control_chars_per_policy() {
+        let input = "a\u{0001}b\u{000c}c";
+
+        let mut kept = NormalizedString::from(input);
+        ControlChars::new(ControlCharPolicy::Keep)
+            .normalize(&mut kept)
+            .unwrap();
+        assert_eq!(kept.get(), input);
+
+        let mut dropped = NormalizedString::from(input);
+        ControlChars::new(ControlCharPolicy::Drop)
+            .normalize(&mut dropped)
+            .unwrap();
+        assert_eq!(dropped.get(), "abc");
+
+        let mut replaced = NormalizedString::from(input);
+        ControlChars::new(ControlCharPolicy::ReplaceWithUnk)
+            .normalize(&mut replaced)
+            .unwrap();
+        assert_eq!(replaced.get(), "a\u{fffd}b\u{fffd}c");
+    }
+}
@@ -1,9 +1,11 @@
 pub mod bert;
+pub mod control_chars;
 pub mod strip;
 pub mod unicode;
 pub mod utils;
 
 pub use crate::normalizers::bert::BertNormalizer;
+pub use crate::normalizers::control_chars::{ControlCharPolicy, ControlChars};
 pub use crate::normalizers::strip::Strip;
 pub use crate::normalizers::unicode::{NFC, NFD, NFKC, NFKD};
 pub use crate::normalizers::utils::{Lowercase, Sequence};
@@ -24,6 +26,7 @@ pub enum NormalizerWrapper {
     NFKD(NFKD),
     Sequence(Sequence),
     Lowercase(Lowercase),
+    ControlChars(ControlChars),
 }
 
 impl Normalizer for NormalizerWrapper {
@@ -37,6 +40,7 @@ impl Normalizer for NormalizerWrapper {
             NormalizerWrapper::NFKD(nfkd) => nfkd.normalize(normalized),
             NormalizerWrapper::Sequence(sequence) => sequence.normalize(normalized),
             NormalizerWrapper::Lowercase(lc) => lc.normalize(normalized),
+            NormalizerWrapper::ControlChars(cc) => cc.normalize(normalized),
         }
     }
 }
@@ -49,3 +53,4 @@ impl_enum_from!(NFD, NormalizerWrapper, NFD);
 impl_enum_from!(Strip, NormalizerWrapper, StripNormalizer);
 impl_enum_from!(Sequence, NormalizerWrapper, Sequence);
 impl_enum_from!(Lowercase, NormalizerWrapper, Lowercase);
+impl_enum_from!(ControlChars, NormalizerWrapper, ControlChars);
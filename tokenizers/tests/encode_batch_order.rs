@@ -0,0 +1,41 @@
+mod common;
+
+use std::collections::HashMap;
+use tokenizers::models::wordlevel::WordLevelBuilder;
+use tokenizers::pre_tokenizers::whitespace::Whitespace;
+use tokenizers::tokenizer::Tokenizer;
+
+fn get_word_level() -> Tokenizer {
+    let mut vocab = HashMap::new();
+    for (i, word) in ["<unk>", "zero", "one", "two", "three", "four"]
+        .iter()
+        .enumerate()
+    {
+        vocab.insert(word.to_string(), i as u32);
+    }
+    let model = WordLevelBuilder::new()
+        .vocab(vocab)
+        .unk_token("<unk>".into())
+        .build();
+
+    let mut tokenizer = Tokenizer::new(model);
+    tokenizer.with_pre_tokenizer(Whitespace::default());
+    tokenizer
+}
+
+#[test]
+fn encode_batch_preserves_input_order() {
+    let tokenizer = get_word_level();
+    let inputs = vec!["four", "zero", "three", "one", "two"];
+
+    let encodings = tokenizer
+        .encode_batch(inputs.clone(), false)
+        .expect("encode_batch should succeed");
+
+    let decoded: Vec<&str> = encodings
+        .iter()
+        .map(|encoding| encoding.get_tokens()[0].as_str())
+        .collect();
+
+    assert_eq!(decoded, inputs);
+}